@@ -0,0 +1,157 @@
+use std::borrow::Cow;
+use wgpu::util::DeviceExt;
+use crate::{
+    color,
+    error::GpuError,
+    gpu::Gpu,
+    pipeline::{PipelineKey, Shader},
+};
+
+/// What the color attachment starts out as before the scene is drawn.
+/// Colors are linear, see `color::from_srgb` for converting picked ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Background {
+    Solid(wgpu::Color),
+    /// Vertical gradient from the top of the screen to the bottom.
+    Gradient {
+        top: wgpu::Color,
+        bottom: wgpu::Color,
+    },
+    /// Keeps the previous contents, for overlays and compositing multiple
+    /// passes. Surfaces only retain them when rendering with MSAA.
+    Load,
+}
+
+/// How `Gpu::render` prepares the attachments of its render pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClearPolicy {
+    pub background: Background,
+    /// Depth to clear to, `None` keeps the previous depth buffer.
+    pub depth: Option<f32>,
+}
+
+impl Default for ClearPolicy {
+    fn default() -> Self {
+        Self {
+            background: Background::Solid(color::from_srgb8(60, 136, 151)),
+            depth: Some(1.0),
+        }
+    }
+}
+
+impl ClearPolicy {
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_depth(mut self, depth: Option<f32>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub(crate) fn color_load_op(&self, format: wgpu::TextureFormat)
+                                -> wgpu::LoadOp<wgpu::Color> {
+        match self.background {
+            Background::Solid(color) => wgpu::LoadOp::Clear(color::for_format(color, format)),
+            // Every pixel gets overwritten by the gradient anyway
+            Background::Gradient { bottom, .. } => {
+                wgpu::LoadOp::Clear(color::for_format(bottom, format))
+            }
+            Background::Load => wgpu::LoadOp::Load,
+        }
+    }
+
+    pub(crate) fn depth_load_op(&self) -> wgpu::LoadOp<f32> {
+        match self.depth {
+            Some(depth) => wgpu::LoadOp::Clear(depth),
+            None => wgpu::LoadOp::Load,
+        }
+    }
+}
+
+/// Fullscreen draw filling the background with a gradient.
+pub(crate) struct GradientPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GradientPass {
+    const SHADER: Shader = Shader {
+        label: "shaders/background.wgsl",
+        source: Cow::Borrowed(include_str!("shaders/background.wgsl")),
+    };
+
+    pub(crate) fn new(gpu: &Gpu, top: wgpu::Color, bottom: wgpu::Color)
+                      -> Result<Self, GpuError> {
+        gpu.scoped("Background gradient", |device| {
+            let format = gpu.config.format;
+            let colors = [
+                color::to_array(color::for_format(top, format)),
+                color::to_array(color::for_format(bottom, format)),
+            ];
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: "Background gradient".into(),
+                contents: bytemuck::cast_slice(&colors),
+                usage: wgpu::BufferUsages::UNIFORM
+            });
+
+            let layout = gpu.pipelines().bind_group_layout(
+                device,
+                "Background gradient layout",
+                &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }]
+            )?;
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: "Background gradient".into(),
+                layout: &layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding()
+                }]
+            });
+
+            let key = PipelineKey {
+                shader: Self::SHADER,
+                vertex_entry: "vs_main",
+                fragment_entry: Some("fs_main"),
+                bind_group_layouts: vec![layout],
+                vertex_layouts: vec![],
+                primitive: wgpu::PrimitiveState::default(),
+                targets: vec![Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL
+                })],
+                // Leaves the depth buffer alone so the scene draws on top
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Gpu::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default()
+                }),
+                sample_count: gpu.sample_count(),
+            };
+
+            let pipeline = gpu.pipelines().render_pipeline(device, gpu.pipeline_cache(), &key)?;
+
+            Ok(Self { pipeline, bind_group })
+        })?
+    }
+
+    pub(crate) fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,112 @@
+use glam::{Mat4, Vec3};
+
+/// How a camera maps view space to clip space. Like the rest of the
+/// renderer it uses left-handed coordinates with depth in `0..1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians.
+        fov_y: f32,
+        near: f32,
+        far: f32,
+    },
+    Orthographic {
+        /// Height of the view volume in world units, the width follows from
+        /// the aspect ratio.
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub projection: Projection,
+    /// Width over height of the surface being rendered to.
+    pub aspect: f32,
+}
+
+impl Camera {
+    pub fn perspective(position: Vec3, target: Vec3, fov_y: f32, aspect: f32) -> Self {
+        Self {
+            position,
+            target,
+            up: Vec3::Y,
+            projection: Projection::Perspective { fov_y, near: 0.01, far: 100.0 },
+            aspect,
+        }
+    }
+
+    pub fn orthographic(position: Vec3, target: Vec3, height: f32, aspect: f32) -> Self {
+        Self {
+            position,
+            target,
+            up: Vec3::Y,
+            projection: Projection::Orthographic { height, near: 0.01, far: 100.0 },
+            aspect,
+        }
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        (self.target - self.position).normalize_or(Vec3::Z)
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at_lh(self.position, self.target, self.up)
+    }
+
+    pub fn projection_matrix(&self) -> Mat4 {
+        match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                Mat4::perspective_lh(fov_y, self.aspect, near, far)
+            }
+            Projection::Orthographic { height, near, far } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+
+                Mat4::orthographic_lh(-half_width, half_width, -half_height, half_height,
+                                      near, far)
+            }
+        }
+    }
+
+    pub fn view_projection_matrix(&self) -> Mat4 {
+        self.projection_matrix() * self.view_matrix()
+    }
+
+    /// Distances to the near and far planes.
+    pub fn depth_range(&self) -> (f32, f32) {
+        match self.projection {
+            Projection::Perspective { near, far, .. }
+            | Projection::Orthographic { near, far, .. } => (near, far),
+        }
+    }
+
+    /// World space corners of the part of the view volume between `near`
+    /// and `far`, near plane first.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vec3; 8] {
+        let projection = match self.projection {
+            Projection::Perspective { fov_y, .. } => Projection::Perspective { fov_y, near, far },
+            Projection::Orthographic { height, .. } => {
+                Projection::Orthographic { height, near, far }
+            }
+        };
+
+        let slice = Camera { projection, ..self.clone() };
+        let inverse = slice.view_projection_matrix().inverse();
+
+        std::array::from_fn(|i| {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            inverse.project_point3(Vec3::new(x, y, z))
+        })
+    }
+}
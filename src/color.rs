@@ -0,0 +1,59 @@
+//! Conversions between sRGB-encoded colors, the way they're usually
+//! written down, and the linear colors the GPU blends and clears with.
+
+/// Decodes one sRGB-encoded channel in `0.0..=1.0` to linear.
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes one linear channel in `0.0..=1.0` as sRGB.
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear color from sRGB channels in `0.0..=1.0`. Alpha is never encoded.
+pub fn from_srgb(r: f64, g: f64, b: f64, a: f64) -> wgpu::Color {
+    wgpu::Color {
+        r: srgb_to_linear(r),
+        g: srgb_to_linear(g),
+        b: srgb_to_linear(b),
+        a,
+    }
+}
+
+/// Linear color from 8-bit sRGB channels, e.g. taken from a color picker.
+pub fn from_srgb8(r: u8, g: u8, b: u8) -> wgpu::Color {
+    from_srgb(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0, 1.0)
+}
+
+/// Encodes a linear color as sRGB, alpha is kept as is.
+pub fn to_srgb(color: wgpu::Color) -> wgpu::Color {
+    wgpu::Color {
+        r: linear_to_srgb(color.r),
+        g: linear_to_srgb(color.g),
+        b: linear_to_srgb(color.b),
+        a: color.a,
+    }
+}
+
+/// What to clear or write a `format` target with for it to show the
+/// linear `color`. sRGB formats encode on write, others need it done here.
+pub fn for_format(color: wgpu::Color, format: wgpu::TextureFormat) -> wgpu::Color {
+    if format.is_srgb() {
+        color
+    } else {
+        to_srgb(color)
+    }
+}
+
+pub fn to_array(color: wgpu::Color) -> [f32; 4] {
+    [color.r as f32, color.g as f32, color.b as f32, color.a as f32]
+}
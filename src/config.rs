@@ -0,0 +1,230 @@
+use std::path::PathBuf;
+use crate::{pipeline_cache::PipelineCache, shadow::ShadowSettings};
+
+/// Presentation settings requested by the application. Everything here is
+/// a preference - `Gpu` falls back to whatever the surface supports and
+/// exposes the values it actually picked.
+#[derive(Debug, Clone)]
+pub struct GpuConfig {
+    /// Present modes in order of preference. `Fifo` is used when none of
+    /// them is supported, as it's the only mode every surface must offer.
+    pub present_modes: Vec<wgpu::PresentMode>,
+    pub surface_format: Option<wgpu::TextureFormat>,
+    pub alpha_mode: Option<wgpu::CompositeAlphaMode>,
+    pub desired_maximum_frame_latency: u32,
+    /// MSAA sample count, lowered to the closest count supported by both
+    /// the surface and the depth format.
+    pub sample_count: u32,
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub force_fallback_adapter: bool,
+    /// Picks the first adapter whose name contains this substring
+    /// (case insensitive) instead of letting wgpu choose.
+    pub adapter_name: Option<String>,
+    /// Minimum limits the engine needs. Device creation fails if the
+    /// adapter can't provide them; otherwise everything the adapter
+    /// supports is requested.
+    pub required_limits: wgpu::Limits,
+    pub required_features: wgpu::Features,
+    /// Features enabled only when the adapter has them. Check
+    /// `Gpu::has_features` before relying on any of these.
+    pub optional_features: wgpu::Features,
+    /// Collects per-pass and per-scope timings, see `Profiler`.
+    pub profiling: bool,
+    /// Where compiled pipelines are persisted between runs, `None` to
+    /// disable. Only used where `Features::PIPELINE_CACHE` is available.
+    pub pipeline_cache_dir: Option<PathBuf>,
+    /// Bytes of per-draw uniforms a single frame can upload.
+    pub uniform_ring_size: u64,
+    /// Lights a lit material can evaluate, extra scene lights are ignored.
+    pub max_lights: u32,
+    pub shadows: ShadowSettings,
+}
+
+impl Default for GpuConfig {
+    fn default() -> Self {
+        Self {
+            present_modes: vec![wgpu::PresentMode::Fifo],
+            surface_format: None,
+            alpha_mode: None,
+            desired_maximum_frame_latency: 2,
+            sample_count: 1,
+            backends: wgpu::Backends::all(),
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            adapter_name: None,
+            required_limits: wgpu::Limits {
+                // Material bind group at 0, lights at 1
+                max_bind_groups: 2,
+                max_vertex_attributes: 5,
+                ..wgpu::Limits::downlevel_webgl2_defaults()
+            },
+            required_features: wgpu::Features::empty(),
+            optional_features: wgpu::Features::TIMESTAMP_QUERY
+                | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS
+                | wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES
+                | wgpu::Features::TEXTURE_COMPRESSION_BC
+                | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+                | wgpu::Features::TEXTURE_COMPRESSION_ASTC
+                | wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::PUSH_CONSTANTS
+                // Needed for format features to report every sample count
+                // the adapter can actually do, not just the WebGPU baseline
+                | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                | wgpu::Features::PIPELINE_CACHE,
+            profiling: false,
+            pipeline_cache_dir: Some(PipelineCache::default_dir()),
+            uniform_ring_size: 1 << 20,
+            max_lights: 16,
+            shadows: ShadowSettings::default(),
+        }
+    }
+}
+
+impl GpuConfig {
+    pub fn with_vsync(mut self, vsync: bool) -> Self {
+        self.present_modes = if vsync {
+            vec![wgpu::PresentMode::Fifo]
+        } else {
+            vec![wgpu::PresentMode::Immediate, wgpu::PresentMode::Mailbox]
+        };
+
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode: wgpu::PresentMode) -> Self {
+        self.present_modes = vec![present_mode];
+        self
+    }
+
+    pub fn with_surface_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.surface_format = Some(format);
+        self
+    }
+
+    pub fn with_alpha_mode(mut self, alpha_mode: wgpu::CompositeAlphaMode) -> Self {
+        self.alpha_mode = Some(alpha_mode);
+        self
+    }
+
+    pub fn with_frame_latency(mut self, frames: u32) -> Self {
+        self.desired_maximum_frame_latency = frames;
+        self
+    }
+
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn with_backends(mut self, backends: wgpu::Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn with_fallback_adapter(mut self, force: bool) -> Self {
+        self.force_fallback_adapter = force;
+        self
+    }
+
+    pub fn with_adapter_name(mut self, name: impl Into<String>) -> Self {
+        self.adapter_name = Some(name.into());
+        self
+    }
+
+    pub fn with_required_limits(mut self, limits: wgpu::Limits) -> Self {
+        self.required_limits = limits;
+        self
+    }
+
+    pub fn with_required_features(mut self, features: wgpu::Features) -> Self {
+        self.required_features = features;
+        self
+    }
+
+    pub fn with_optional_features(mut self, features: wgpu::Features) -> Self {
+        self.optional_features = features;
+        self
+    }
+
+    pub fn with_profiling(mut self, profiling: bool) -> Self {
+        self.profiling = profiling;
+        self
+    }
+
+    pub fn with_pipeline_cache_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.pipeline_cache_dir = dir;
+        self
+    }
+
+    pub fn with_uniform_ring_size(mut self, size: u64) -> Self {
+        self.uniform_ring_size = size;
+        self
+    }
+
+    pub fn with_max_lights(mut self, max_lights: u32) -> Self {
+        self.max_lights = max_lights;
+        self
+    }
+
+    pub fn with_shadows(mut self, shadows: ShadowSettings) -> Self {
+        self.shadows = shadows;
+        self
+    }
+
+    /// Overrides adapter selection from the environment:
+    /// `WGPU_BACKEND` (e.g. `vulkan,gl`), `WGPU_POWER_PREF` (`low`/`high`),
+    /// `WGPU_ADAPTER_NAME` and `WGPU_FORCE_FALLBACK_ADAPTER` (`1`/`true`).
+    /// `WEBGPU_PROFILE` (`1`/`true`) turns on the profiler.
+    pub fn with_env_overrides(mut self) -> Self {
+        if let Some(backends) = wgpu::Backends::from_env() {
+            self.backends = backends;
+        }
+
+        if let Some(power_preference) = wgpu::PowerPreference::from_env() {
+            self.power_preference = power_preference;
+        }
+
+        if let Ok(name) = std::env::var("WGPU_ADAPTER_NAME") {
+            self.adapter_name = Some(name);
+        }
+
+        if let Ok(force) = std::env::var("WGPU_FORCE_FALLBACK_ADAPTER") {
+            self.force_fallback_adapter = matches!(force.to_lowercase().as_str(), "1" | "true");
+        }
+
+        if let Ok(profile) = std::env::var("WEBGPU_PROFILE") {
+            self.profiling = matches!(profile.to_lowercase().as_str(), "1" | "true");
+        }
+
+        self
+    }
+
+    pub(crate) fn pick_present_mode(&self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        self.present_modes
+            .iter()
+            .find(|mode| supported.contains(mode))
+            .copied()
+            .unwrap_or(wgpu::PresentMode::Fifo)
+    }
+
+    pub(crate) fn pick_surface_format(&self, supported: &[wgpu::TextureFormat])
+                                      -> wgpu::TextureFormat {
+        self.surface_format
+            .filter(|format| supported.contains(format))
+            .or_else(|| supported.iter().find(|f| f.is_srgb()).copied())
+            .unwrap_or(supported[0])
+    }
+
+    pub(crate) fn pick_alpha_mode(&self, supported: &[wgpu::CompositeAlphaMode])
+                                  -> wgpu::CompositeAlphaMode {
+        self.alpha_mode
+            .filter(|mode| supported.contains(mode))
+            .unwrap_or(supported[0])
+    }
+}
@@ -0,0 +1,282 @@
+use std::{collections::HashSet, f32::consts::FRAC_PI_2};
+use glam::{Vec2, Vec3};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+use crate::camera::Camera;
+
+// Keeps the camera from flipping over when looking straight up or down
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+// Scroll wheels on some platforms report pixels rather than lines
+const PIXELS_PER_LINE: f32 = 40.0;
+
+/// Moves a camera in response to window input.
+pub trait CameraController {
+    /// Returns `true` if the event was used by the controller.
+    fn window_event(&mut self, event: &WindowEvent) -> bool;
+    /// Applies the input gathered since the last call, `dt` is in seconds.
+    fn update(&mut self, camera: &mut Camera, dt: f32);
+}
+
+// Unit vector pointing along `yaw` (around Y, starting at +Z) and `pitch`
+fn direction(yaw: f32, pitch: f32) -> Vec3 {
+    Vec3::new(pitch.cos() * yaw.sin(), pitch.sin(), pitch.cos() * yaw.cos())
+}
+
+fn yaw_pitch(direction: Vec3) -> (f32, f32) {
+    let direction = direction.normalize_or(Vec3::Z);
+    (direction.x.atan2(direction.z), direction.y.clamp(-1.0, 1.0).asin())
+}
+
+fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, y) => *y,
+        MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+    }
+}
+
+/// Tracks cursor movement between events.
+#[derive(Default)]
+struct Cursor {
+    last: Option<PhysicalPosition<f64>>,
+    delta: Vec2,
+}
+
+impl Cursor {
+    fn moved(&mut self, position: PhysicalPosition<f64>, tracking: bool) {
+        if tracking && let Some(last) = self.last {
+            self.delta += Vec2::new((position.x - last.x) as f32, (position.y - last.y) as f32);
+        }
+
+        self.last = Some(position);
+    }
+
+    fn take_delta(&mut self) -> Vec2 {
+        std::mem::take(&mut self.delta)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitSettings {
+    /// Radians per pixel dragged.
+    pub rotate_sensitivity: f32,
+    /// Fraction of the orbit distance moved per pixel dragged.
+    pub pan_speed: f32,
+    /// Fraction of the orbit distance moved per scroll line.
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+}
+
+impl Default for OrbitSettings {
+    fn default() -> Self {
+        Self {
+            rotate_sensitivity: 0.005,
+            pan_speed: 0.002,
+            zoom_speed: 0.1,
+            min_distance: 0.05,
+        }
+    }
+}
+
+/// Orbits around the camera target: left drag rotates, right or middle
+/// drag pans and the scroll wheel zooms.
+pub struct OrbitController {
+    pub settings: OrbitSettings,
+    cursor: Cursor,
+    rotating: bool,
+    panning: bool,
+    scroll: f32,
+}
+
+impl OrbitController {
+    pub fn new(settings: OrbitSettings) -> Self {
+        Self {
+            settings,
+            cursor: Cursor::default(),
+            rotating: false,
+            panning: false,
+            scroll: 0.0,
+        }
+    }
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self::new(OrbitSettings::default())
+    }
+}
+
+impl CameraController for OrbitController {
+    fn window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.rotating = pressed,
+                    MouseButton::Right | MouseButton::Middle => self.panning = pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor.moved(*position, self.rotating || self.panning);
+                self.rotating || self.panning
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += scroll_lines(delta);
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.rotating = false;
+                self.panning = false;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, _dt: f32) {
+        let delta = self.cursor.take_delta();
+        let scroll = std::mem::take(&mut self.scroll);
+
+        let offset = camera.position - camera.target;
+        let (mut yaw, mut pitch) = yaw_pitch(offset);
+        let mut distance = offset.length();
+
+        if self.rotating {
+            yaw += delta.x * self.settings.rotate_sensitivity;
+            pitch = (pitch + delta.y * self.settings.rotate_sensitivity)
+                .clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        if self.panning {
+            let forward = camera.forward();
+            let right = camera.up.cross(forward).normalize_or(Vec3::X);
+            let up = forward.cross(right);
+            let scale = self.settings.pan_speed * distance;
+
+            camera.target += (up * delta.y - right * delta.x) * scale;
+        }
+
+        distance = (distance * (-scroll * self.settings.zoom_speed).exp())
+            .max(self.settings.min_distance);
+
+        camera.position = camera.target + direction(yaw, pitch) * distance;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlySettings {
+    /// World units per second.
+    pub move_speed: f32,
+    /// Speed multiplier while shift is held.
+    pub sprint_multiplier: f32,
+    /// Radians per pixel the mouse moves.
+    pub look_sensitivity: f32,
+}
+
+impl Default for FlySettings {
+    fn default() -> Self {
+        Self {
+            move_speed: 2.0,
+            sprint_multiplier: 4.0,
+            look_sensitivity: 0.003,
+        }
+    }
+}
+
+/// Free-flying camera: WASD moves, Q/E go down and up, shift speeds up
+/// and dragging with the right mouse button looks around.
+pub struct FlyController {
+    pub settings: FlySettings,
+    cursor: Cursor,
+    looking: bool,
+    held_keys: HashSet<KeyCode>,
+}
+
+impl FlyController {
+    pub fn new(settings: FlySettings) -> Self {
+        Self {
+            settings,
+            cursor: Cursor::default(),
+            looking: false,
+            held_keys: HashSet::new(),
+        }
+    }
+
+    fn axis(&self, positive: KeyCode, negative: KeyCode) -> f32 {
+        self.held_keys.contains(&positive) as i32 as f32
+            - self.held_keys.contains(&negative) as i32 as f32
+    }
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self::new(FlySettings::default())
+    }
+}
+
+impl CameraController for FlyController {
+    fn window_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return false;
+                };
+
+                match event.state {
+                    ElementState::Pressed => self.held_keys.insert(code),
+                    ElementState::Released => self.held_keys.remove(&code),
+                };
+
+                matches!(code, KeyCode::KeyW | KeyCode::KeyA | KeyCode::KeyS | KeyCode::KeyD
+                         | KeyCode::KeyQ | KeyCode::KeyE
+                         | KeyCode::ShiftLeft | KeyCode::ShiftRight)
+            }
+            WindowEvent::MouseInput { state, button: MouseButton::Right, .. } => {
+                self.looking = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor.moved(*position, self.looking);
+                self.looking
+            }
+            // Key releases are lost while unfocused, don't keep flying
+            WindowEvent::Focused(false) => {
+                self.held_keys.clear();
+                self.looking = false;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, camera: &mut Camera, dt: f32) {
+        let delta = self.cursor.take_delta();
+        let (mut yaw, mut pitch) = yaw_pitch(camera.forward());
+
+        if self.looking {
+            yaw += delta.x * self.settings.look_sensitivity;
+            pitch = (pitch - delta.y * self.settings.look_sensitivity)
+                .clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        let forward = direction(yaw, pitch);
+        let right = camera.up.cross(forward).normalize_or(Vec3::X);
+
+        let movement = forward * self.axis(KeyCode::KeyW, KeyCode::KeyS)
+            + right * self.axis(KeyCode::KeyD, KeyCode::KeyA)
+            + camera.up * self.axis(KeyCode::KeyE, KeyCode::KeyQ);
+
+        let sprinting = self.held_keys.contains(&KeyCode::ShiftLeft)
+            || self.held_keys.contains(&KeyCode::ShiftRight);
+        let speed = self.settings.move_speed
+            * if sprinting { self.settings.sprint_multiplier } else { 1.0 };
+
+        camera.position += movement.normalize_or_zero() * speed * dt;
+        camera.target = camera.position + forward;
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+/// A wgpu error caught while creating a labelled GPU resource.
+#[derive(Debug)]
+pub enum GpuError {
+    Validation { label: String, description: String },
+    OutOfMemory { label: String },
+    Internal { label: String, description: String },
+}
+
+impl GpuError {
+    fn new(label: &str, error: wgpu::Error) -> Self {
+        let label = label.to_owned();
+
+        match error {
+            wgpu::Error::Validation { description, .. } => Self::Validation { label, description },
+            wgpu::Error::OutOfMemory { .. } => Self::OutOfMemory { label },
+            wgpu::Error::Internal { description, .. } => Self::Internal { label, description },
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Validation { label, .. }
+            | Self::OutOfMemory { label }
+            | Self::Internal { label, .. } => label,
+        }
+    }
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Validation { label, description } => {
+                write!(f, "Validation error in {label}: {description}")
+            }
+            Self::OutOfMemory { label } => write!(f, "Out of memory while creating {label}"),
+            Self::Internal { label, description } => {
+                write!(f, "Internal error in {label}: {description}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+/// Runs `create` inside validation, out-of-memory and internal error
+/// scopes, turning anything wgpu reports into a `GpuError` instead of
+/// letting it reach the uncaptured error handler.
+pub fn scoped<T>(device: &wgpu::Device, label: &str, create: impl FnOnce() -> T)
+                 -> Result<T, GpuError> {
+    device.push_error_scope(wgpu::ErrorFilter::Internal);
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let value = create();
+
+    // Always pop all three scopes, even when the first one caught something
+    let validation = pollster::block_on(device.pop_error_scope());
+    let out_of_memory = pollster::block_on(device.pop_error_scope());
+    let internal = pollster::block_on(device.pop_error_scope());
+
+    match validation.or(out_of_memory).or(internal) {
+        Some(error) => Err(GpuError::new(label, error)),
+        None => Ok(value),
+    }
+}
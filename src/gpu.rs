@@ -1,11 +1,65 @@
 use winit::{window::Window, dpi::PhysicalSize};
-use anyhow::Result;
-use std::sync::Arc;
+use anyhow::{Result, anyhow, bail};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use crate::{
+    background::{Background, ClearPolicy, GradientPass},
+    camera::Camera,
+    config::GpuConfig,
+    error::{self, GpuError},
+    light::{Light, LightBuffer},
+    pipeline::PipelineRegistry,
+    pipeline_cache::PipelineCache,
+    profiler::Profiler,
+    readback::{BlitPass, PendingReadback},
+    shadow::{ShadowCaster, ShadowPass},
+    target::{Frame, RenderTarget},
+    texture::TextureRegistry,
+    upload::FrameUploads,
+};
+
+/// What happened to the frame requested from `Gpu::render`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderStatus {
+    Presented,
+    /// The surface was lost or outdated and had to be reconfigured before
+    /// the frame could be drawn.
+    Recovered,
+    /// No surface texture was available, nothing was drawn this time.
+    Skipped,
+}
+
+/// Per-frame state handed to the callback recording the render pass.
+pub struct RenderContext<'a> {
+    pub uploads: &'a mut FrameUploads,
+    pub profiler: &'a mut Profiler,
+    /// Set while a shadow map is drawn, the callback should then draw
+    /// every shadow caster through it instead of its usual draws.
+    pub shadow: Option<ShadowPass<'a>>,
+}
 
 pub struct Gpu {
-    window: Arc<Window>,
-    surface: wgpu::Surface<'static>,
+    instance: wgpu::Instance,
+    target: RenderTarget,
     depth: wgpu::TextureView,
+    msaa: Option<wgpu::TextureView>,
+    sample_count: u32,
+    device_lost: Arc<AtomicBool>,
+    settings: GpuConfig,
+    adapter_info: wgpu::AdapterInfo,
+    capture_requested: bool,
+    capture: Option<image::RgbaImage>,
+    profiler: Profiler,
+    pipeline_cache: Option<PipelineCache>,
+    pipelines: PipelineRegistry,
+    textures: TextureRegistry,
+    clear: ClearPolicy,
+    gradient: Option<GradientPass>,
+    uploads: FrameUploads,
+    lights: LightBuffer,
+    shadow_caster: ShadowCaster,
 
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
@@ -13,8 +67,13 @@ pub struct Gpu {
 }
 
 impl Gpu {
-    fn get_instance() -> wgpu::Instance {
-        let descriptor = wgpu::InstanceDescriptor::default();
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+
+    fn get_instance(settings: &GpuConfig) -> wgpu::Instance {
+        let descriptor = wgpu::InstanceDescriptor {
+            backends: settings.backends,
+            ..Default::default()
+        };
         wgpu::Instance::new(&descriptor)
     }
 
@@ -22,70 +81,253 @@ impl Gpu {
         adapter: &wgpu::Adapter,
         surface: &wgpu::Surface<'static>,
         size: PhysicalSize<u32>,
+        settings: &GpuConfig,
     ) -> wgpu::SurfaceConfiguration {
-        let capabilities = surface.get_capabilities(&adapter);
+        let capabilities = surface.get_capabilities(adapter);
 
-        let surface_format = capabilities
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(capabilities.formats[0]);
+        // Lets screenshots copy straight out of the swapchain; without it
+        // captured frames are drawn into an intermediate texture instead
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (capabilities.usages & wgpu::TextureUsages::COPY_SRC);
+
+        let present_mode = settings.pick_present_mode(&capabilities.present_modes);
+        if settings.present_modes.first() != Some(&present_mode) {
+            log::warn!("Requested present modes {:?} are unsupported, using {present_mode:?}",
+                       settings.present_modes);
+        }
 
         wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
+            usage,
+            format: settings.pick_surface_format(&capabilities.formats),
+            width: size.width,
+            height: size.height,
+            present_mode,
+            alpha_mode: settings.pick_alpha_mode(&capabilities.alpha_modes),
+            view_formats: vec![],
+            desired_maximum_frame_latency: settings.desired_maximum_frame_latency,
+        }
+    }
+
+    fn get_headless_config(size: PhysicalSize<u32>, settings: &GpuConfig)
+                           -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
             width: size.width,
             height: size.height,
-            present_mode: capabilities.present_modes[0],
-            alpha_mode: capabilities.alpha_modes[0],
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
             view_formats: vec![],
-            desired_maximum_frame_latency: 2,
+            desired_maximum_frame_latency: settings.desired_maximum_frame_latency,
+        }
+    }
+
+    fn get_limits(adapter: &wgpu::Adapter, settings: &GpuConfig) -> Result<wgpu::Limits> {
+        let supported = adapter.limits();
+
+        let mut missing = Vec::new();
+        settings.required_limits.check_limits_with_fail_fn(
+            &supported,
+            false,
+            |name, required, allowed| {
+                missing.push(format!("{name} (needs {required}, has {allowed})"));
+            }
+        );
+
+        if !missing.is_empty() {
+            bail!("Adapter doesn't meet the required limits: {}", missing.join(", "));
+        }
+
+        Ok(supported)
+    }
+
+    fn get_features(adapter: &wgpu::Adapter, settings: &GpuConfig) -> Result<wgpu::Features> {
+        let supported = adapter.features();
+
+        let missing = settings.required_features - supported;
+        if !missing.is_empty() {
+            bail!("Adapter doesn't support the required features: {missing:?}");
         }
+
+        let optional = settings.optional_features & supported;
+        log::info!("Enabled optional features: {optional:?}");
+
+        Ok(settings.required_features | optional)
     }
 
-    fn get_limits() -> wgpu::Limits {
-        let mut limits = wgpu::Limits::defaults();
-        limits.max_vertex_attributes = 5;
-        limits.max_bind_groups = 1;
+    fn find_adapter_by_name(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface<'static>>,
+        settings: &GpuConfig,
+        name: &str,
+    ) -> Result<wgpu::Adapter> {
+        let name = name.to_lowercase();
+        let adapters = instance.enumerate_adapters(settings.backends);
+
+        let names: Vec<_> = adapters.iter().map(|a| a.get_info().name).collect();
 
-        limits
+        adapters
+            .into_iter()
+            .filter(|adapter| surface.is_none_or(|s| adapter.is_surface_supported(s)))
+            .find(|adapter| adapter.get_info().name.to_lowercase().contains(&name))
+            .ok_or_else(|| anyhow!("No adapter matching {name:?}, available: {names:?}"))
     }
 
     async fn get_adapter(
         instance: &wgpu::Instance,
-        surface: &wgpu::Surface<'static>,
+        surface: Option<&wgpu::Surface<'static>>,
+        settings: &GpuConfig,
+        force_fallback_adapter: bool,
     ) -> Result<wgpu::Adapter> {
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(surface),
-                force_fallback_adapter: false,
-            })
-            .await?;
+        let adapter = if let Some(name) = &settings.adapter_name {
+            Self::find_adapter_by_name(instance, surface, settings, name)?
+        } else {
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: settings.power_preference,
+                    compatible_surface: surface,
+                    force_fallback_adapter,
+                })
+                .await?
+        };
+
+        let info = adapter.get_info();
+        log::info!("Using adapter {:?} ({:?}, {:?}), driver: {} {}",
+                   info.name, info.device_type, info.backend, info.driver, info.driver_info);
 
         Ok(adapter)
     }
 
-    async fn get_device(adapter: &wgpu::Adapter, limits: wgpu::Limits)
+    // Prefer the software adapter so headless renders work on machines
+    // without a GPU, but don't refuse to run when only a real one exists.
+    async fn get_headless_adapter(instance: &wgpu::Instance, settings: &GpuConfig)
+                                  -> Result<wgpu::Adapter> {
+        match Self::get_adapter(instance, None, settings, true).await {
+            Ok(adapter) => Ok(adapter),
+            Err(err) if !settings.force_fallback_adapter => {
+                log::warn!("No fallback adapter available ({err}), using the default one");
+                Self::get_adapter(instance, None, settings, false).await
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn get_device(adapter: &wgpu::Adapter, settings: &GpuConfig)
                         -> Result<(wgpu::Device, wgpu::Queue)> {
-        let mut descriptor = wgpu::DeviceDescriptor::default();
-        descriptor.required_limits = limits;
+        let descriptor = wgpu::DeviceDescriptor {
+            required_features: Self::get_features(adapter, settings)?,
+            required_limits: Self::get_limits(adapter, settings)?,
+            ..Default::default()
+        };
         let (device, queue) = adapter.request_device(&descriptor).await?;
 
-        device.set_device_lost_callback(|reason, message| {
-            eprintln!("{:?}", reason);
-            eprintln!("{message}");
-        });
+        device.on_uncaptured_error(Box::new(|error| {
+            log::error!("Uncaptured GPU error: {error}");
+        }));
 
         queue.on_submitted_work_done(|| println!("Finished!"));
 
         Ok((device, queue))
     }
 
+    // Every device gets its own flag, so the callback fired when a lost
+    // device is finally dropped doesn't mark its replacement as lost.
+    fn watch_device_lost(device: &wgpu::Device) -> Arc<AtomicBool> {
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let flag = device_lost.clone();
+
+        device.set_device_lost_callback(move |reason, message| {
+            log::error!("GPU device lost ({reason:?}): {message}");
+            flag.store(true, Ordering::Release);
+        });
+
+        device_lost
+    }
+
+    fn get_pipeline_cache(device: &wgpu::Device,
+                          adapter_info: &wgpu::AdapterInfo,
+                          settings: &GpuConfig) -> Option<PipelineCache> {
+        let dir = settings.pipeline_cache_dir.as_deref()?;
+        PipelineCache::load(device, adapter_info, dir)
+    }
+
+    fn make_light_buffer(device: &wgpu::Device, settings: &GpuConfig) -> Result<LightBuffer> {
+        let limits = device.limits();
+        let shadows = &settings.shadows;
+
+        let size = LightBuffer::buffer_size(settings.max_lights, shadows);
+        let limit = limits.max_uniform_buffer_binding_size as u64;
+        if size > limit {
+            bail!("{} lights and {} shadow maps need a {size} byte uniform buffer, \
+                   the device allows {limit}", settings.max_lights, shadows.max_maps);
+        }
+
+        if shadows.map_size > limits.max_texture_dimension_2d {
+            bail!("{0}x{0} shadow maps exceed the device's {1} texel limit",
+                  shadows.map_size, limits.max_texture_dimension_2d);
+        }
+
+        if shadows.max_maps.max(2) > limits.max_texture_array_layers {
+            bail!("{} shadow maps exceed the device's {} texture array layers",
+                  shadows.max_maps, limits.max_texture_array_layers);
+        }
+
+        Ok(error::scoped(device, "Light buffer", || {
+            LightBuffer::new(device, settings.max_lights, settings.shadows)
+        })?)
+    }
+
+    fn make_shadow_caster(device: &wgpu::Device,
+                          pipelines: &PipelineRegistry,
+                          pipeline_cache: Option<&PipelineCache>,
+                          uploads: &FrameUploads,
+                          settings: &GpuConfig) -> Result<ShadowCaster, GpuError> {
+        error::scoped(device, "Shadow caster", || {
+            ShadowCaster::new(device, pipelines, pipeline_cache.map(PipelineCache::get),
+                              uploads, &settings.shadows)
+        })?
+    }
+
+    // Adapter specific sample counts only apply when the device enabled
+    // them, otherwise just the ones every adapter guarantees can be used
+    fn format_flags(adapter: &wgpu::Adapter, device: &wgpu::Device,
+                    format: wgpu::TextureFormat) -> wgpu::TextureFormatFeatureFlags {
+        let features = device.features();
+
+        if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            adapter.get_texture_format_features(format).flags
+        } else {
+            format.guaranteed_format_features(features).flags
+        }
+    }
+
+    fn get_sample_count(adapter: &wgpu::Adapter,
+                        device: &wgpu::Device,
+                        format: wgpu::TextureFormat,
+                        settings: &GpuConfig) -> u32 {
+        let color = Self::format_flags(adapter, device, format);
+        let depth = Self::format_flags(adapter, device, Self::DEPTH_FORMAT);
+
+        let sample_count = [8, 4, 2, 1]
+            .into_iter()
+            .filter(|&count| count <= settings.sample_count)
+            .find(|&count| color.sample_count_supported(count)
+                  && depth.sample_count_supported(count))
+            .unwrap_or(1);
+
+        if sample_count != settings.sample_count {
+            log::warn!("{}x MSAA is unsupported for {format:?}, using {sample_count}x",
+                       settings.sample_count);
+        }
+
+        sample_count
+    }
+
     fn make_depth_texture(device: &wgpu::Device,
                           config: &wgpu::SurfaceConfiguration,
-                          format: &wgpu::TextureFormat)
+                          format: &wgpu::TextureFormat,
+                          sample_count: u32)
     -> (wgpu::Texture, wgpu::TextureView) {
         let sz = wgpu::Extent3d {
             width: config.width,
@@ -93,15 +335,22 @@ impl Gpu {
             depth_or_array_layers: 1
         };
         
+        // Binding a multisampled depth buffer isn't needed and mixing it
+        // with the (renderbuffer backed) MSAA color target breaks on GL
+        let usage = if sample_count == 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        };
+
         let texture_desc = wgpu::TextureDescriptor {
             label: "Depth Texture".into(),
             dimension: wgpu::TextureDimension::D2,
             size: sz,
             mip_level_count: 1,
-            sample_count: 1,
-            format: format.clone(),
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count,
+            format: *format,
+            usage,
             view_formats: &[]
         };
 
@@ -112,40 +361,354 @@ impl Gpu {
         (texture, view)
     }
 
-    pub async fn new(window: Window, size: PhysicalSize<u32>) -> Result<Self> {
-        let window = Arc::new(window);
-        
-        let instance = Self::get_instance();
-        let surface = instance.create_surface(window.clone())?;
-        let adapter = Self::get_adapter(&instance, &surface).await?;
-        let limits = Self::get_limits();
-        let (device, queue) = Self::get_device(&adapter, limits).await?;
+    fn make_msaa_texture(device: &wgpu::Device,
+                         config: &wgpu::SurfaceConfiguration,
+                         sample_count: u32) -> Option<wgpu::TextureView> {
+        if sample_count == 1 {
+            return None;
+        }
+
+        let texture_desc = wgpu::TextureDescriptor {
+            label: "Multisampled color texture".into(),
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1
+            },
+            mip_level_count: 1,
+            sample_count,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[]
+        };
+
+        let texture = device.create_texture(&texture_desc);
+
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
+    fn make_attachments(device: &wgpu::Device,
+                        config: &wgpu::SurfaceConfiguration,
+                        sample_count: u32)
+    -> Result<(wgpu::TextureView, Option<wgpu::TextureView>), GpuError> {
+        error::scoped(device, "Depth and MSAA attachments", || {
+            let (_, depth) = Self::make_depth_texture(device, config, &Self::DEPTH_FORMAT, sample_count);
+            let msaa = Self::make_msaa_texture(device, config, sample_count);
+
+            (depth, msaa)
+        })
+    }
 
-        let config = Self::get_config(&adapter, &surface, size);
-        surface.configure(&device, &config);
+    // Creates everything that lives on `device` and hands `target` over to
+    // it, shared by every way of getting a device
+    fn build(instance: wgpu::Instance,
+             adapter: &wgpu::Adapter,
+             device: wgpu::Device,
+             queue: wgpu::Queue,
+             mut target: RenderTarget,
+             config: wgpu::SurfaceConfiguration,
+             settings: GpuConfig) -> Result<Self> {
+        let device_lost = Self::watch_device_lost(&device);
+        target.configure(&device, &config)?;
 
-        let (_, depth) = Self::make_depth_texture(&device, &config, &wgpu::TextureFormat::Depth24Plus);
+        let sample_count = Self::get_sample_count(adapter, &device, config.format, &settings);
+        let (depth, msaa) = Self::make_attachments(&device, &config, sample_count)?;
+        let profiler = Profiler::new(&device, &queue, settings.profiling);
+        let adapter_info = adapter.get_info();
+        let pipeline_cache = Self::get_pipeline_cache(&device, &adapter_info, &settings);
+        let uploads = FrameUploads::new(&device, settings.uniform_ring_size);
+        let lights = Self::make_light_buffer(&device, &settings)?;
+        let pipelines = PipelineRegistry::default();
+        let shadow_caster = Self::make_shadow_caster(&device, &pipelines, pipeline_cache.as_ref(),
+                                                     &uploads, &settings)?;
 
         Ok(Self {
-            window,
-            surface,
+            instance,
+            target,
             depth,
+            msaa,
+            sample_count,
+            device_lost,
+            settings,
+            adapter_info,
+            capture_requested: false,
+            capture: None,
+            profiler,
+            pipeline_cache,
+            pipelines,
+            textures: TextureRegistry::default(),
+            clear: ClearPolicy::default(),
+            gradient: None,
+            uploads,
+            lights,
+            shadow_caster,
             device,
             queue,
             config,
         })
     }
 
-    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+    pub async fn new(window: Window, size: PhysicalSize<u32>, settings: GpuConfig)
+                     -> Result<Self> {
+        let window = Arc::new(window);
+        
+        let instance = Self::get_instance(&settings);
+        let surface = instance.create_surface(window.clone())?;
+        let adapter = Self::get_adapter(
+            &instance,
+            Some(&surface),
+            &settings,
+            settings.force_fallback_adapter
+        ).await?;
+        let (device, queue) = Self::get_device(&adapter, &settings).await?;
+
+        let config = Self::get_config(&adapter, &surface, size, &settings);
+        let target = RenderTarget::Surface { window, surface };
+
+        Self::build(instance, &adapter, device, queue, target, config, settings)
+    }
+
+    pub async fn new_headless(size: PhysicalSize<u32>, settings: GpuConfig) -> Result<Self> {
+        let instance = Self::get_instance(&settings);
+        let adapter = Self::get_headless_adapter(&instance, &settings).await?;
+        let (device, queue) = Self::get_device(&adapter, &settings).await?;
+
+        let config = Self::get_headless_config(size, &settings);
+        let target = RenderTarget::offscreen(&device, &config)?;
+
+        Self::build(instance, &adapter, device, queue, target, config, settings)
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) -> Result<()> {
+        // Minimized windows report a zero size, which is not a valid
+        // surface configuration - keep the old one until we're restored.
+        if size.width == 0 || size.height == 0 {
+            return Ok(());
+        }
+
         self.config.width = size.width;
         self.config.height = size.height;
+        self.target.configure(&self.device, &self.config)?;
+        (self.depth, self.msaa) = Self::make_attachments(&self.device, &self.config, self.sample_count)?;
+
+        Ok(())
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
+    }
+
+    /// Requests a fresh adapter, device and queue after the old device was
+    /// lost and reconfigures the render target for them. Every resource
+    /// created from the old device has to be rebuilt by its owner.
+    pub async fn recover(&mut self) -> Result<()> {
+        let adapter = match &self.target {
+            RenderTarget::Surface { surface, .. } => {
+                Self::get_adapter(
+                    &self.instance,
+                    Some(surface),
+                    &self.settings,
+                    self.settings.force_fallback_adapter
+                ).await?
+            }
+            RenderTarget::Offscreen { .. } => {
+                Self::get_headless_adapter(&self.instance, &self.settings).await?
+            }
+        };
+
+        let (device, queue) = Self::get_device(&adapter, &self.settings).await?;
+
+        // Everything is built against a stand-in target first, so a failure
+        // leaves `self` on the old device instead of a mix of both
+        let stand_in = RenderTarget::offscreen(&device, &self.config)?;
+        let mut gpu = Self::build(self.instance.clone(), &adapter, device, queue, stand_in,
+                                  self.config.clone(), self.settings.clone())?;
+        gpu.set_clear_policy(self.clear)?;
+        self.target.configure(&gpu.device, &gpu.config)?;
+
+        std::mem::swap(&mut gpu.target, &mut self.target);
+        gpu.capture_requested = self.capture_requested;
+        gpu.textures = std::mem::take(&mut self.textures);
+        gpu.textures.clear_textures();
+        *self = gpu;
+
+        Ok(())
+    }
+
+    pub fn settings(&self) -> &GpuConfig {
+        &self.settings
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// Creates resources with `create`, catching any validation or
+    /// out-of-memory error wgpu raises as a `GpuError` labelled `label`.
+    pub fn scoped<T>(&self, label: &str, create: impl FnOnce(&wgpu::Device) -> T)
+                     -> Result<T, GpuError> {
+        error::scoped(&self.device, label, || create(&self.device))
+    }
+
+    pub fn limits(&self) -> wgpu::Limits {
+        self.device.limits()
+    }
+
+    /// Whether all of the given (optional) features were enabled on the device.
+    pub fn has_features(&self, features: wgpu::Features) -> bool {
+        self.device.features().contains(features)
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    pub fn alpha_mode(&self) -> wgpu::CompositeAlphaMode {
+        self.config.alpha_mode
+    }
+
+    pub fn frame_latency(&self) -> u32 {
+        self.config.desired_maximum_frame_latency
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.config.width as f32 / self.config.height as f32
+    }
+
+    fn acquire_frame(&self) -> Result<Option<(Frame, RenderStatus)>> {
+        let mut status = RenderStatus::Presented;
+
+        let result = match self.target.acquire() {
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.target.reconfigure(&self.device, &self.config);
+                status = RenderStatus::Recovered;
+                self.target.acquire()
+            }
+            result => result,
+        };
+
+        match result {
+            Ok(frame) => Ok(Some((frame, status))),
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                Err(anyhow!(wgpu::SurfaceError::OutOfMemory))
+            }
+            Err(err) => {
+                log::warn!("Skipping frame: {err}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Makes the next `render` call copy its frame back to the CPU, to be
+    /// picked up with `take_capture`.
+    pub fn request_capture(&mut self) {
+        self.capture_requested = true;
+    }
+
+    pub fn take_capture(&mut self) -> Option<image::RgbaImage> {
+        self.capture.take()
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    /// Cache to pass to pipeline descriptors, if the device supports one.
+    pub fn pipeline_cache(&self) -> Option<&wgpu::PipelineCache> {
+        self.pipeline_cache.as_ref().map(PipelineCache::get)
+    }
+
+    pub fn uploads(&self) -> &FrameUploads {
+        &self.uploads
+    }
+
+    pub fn lights(&self) -> &LightBuffer {
+        &self.lights
     }
 
-    pub fn render(&self, mut set_render_pass: impl FnMut(&mut wgpu::RenderPass)) -> Result<()> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    /// Uploads the lights the next frames are lit with, fitting the shadow
+    /// maps of directional lights to `camera`.
+    pub fn set_lights(&mut self, ambient: glam::Vec3, lights: &[Light], camera: &Camera) {
+        self.lights.write(&self.queue, ambient, lights, camera);
+    }
+
+    pub fn pipelines(&self) -> &PipelineRegistry {
+        &self.pipelines
+    }
+
+    pub fn textures(&self) -> &TextureRegistry {
+        &self.textures
+    }
+
+    fn make_gradient(&self) -> Result<Option<GradientPass>, GpuError> {
+        match self.clear.background {
+            Background::Gradient { top, bottom } => Ok(Some(GradientPass::new(self, top, bottom)?)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn clear_policy(&self) -> ClearPolicy {
+        self.clear
+    }
+
+    pub fn set_clear_policy(&mut self, clear: ClearPolicy) -> Result<()> {
+        self.clear = clear;
+        self.gradient = self.make_gradient()?;
+
+        Ok(())
+    }
+
+    /// Writes the pipelines compiled so far to the cache directory.
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        match &self.pipeline_cache {
+            Some(cache) => cache.save(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn render(&mut self, mut set_render_pass: impl FnMut(&mut wgpu::RenderPass, &mut RenderContext))
+                  -> Result<RenderStatus> {
+        self.profiler.begin_frame(&self.device);
+        self.uploads.begin_frame();
+
+        let Some((frame, status)) = self.acquire_frame()? else {
+            // A later frame shouldn't stall on a capture nobody waits for
+            self.capture_requested = false;
+            self.target.request_redraw();
+            return Ok(RenderStatus::Skipped);
+        };
+
+        // Captured frames go to an intermediate texture when the surface
+        // can't be copied from, which is then drawn onto the surface
+        let capture_texture = (self.capture_requested
+                               && !self.config.usage.contains(wgpu::TextureUsages::COPY_SRC))
+            .then(|| {
+                let config = wgpu::SurfaceConfiguration {
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::COPY_SRC
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    ..self.config.clone()
+                };
+
+                self.scoped("Capture texture", |device| {
+                    RenderTarget::make_offscreen_texture(device, &config)
+                })
+            })
+            .transpose()?;
+
+        let capture_view = capture_texture.as_ref()
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        let view = capture_view.as_ref().unwrap_or(&frame.view);
+        let blit = capture_view.as_ref().map(|view| BlitPass::new(self, view)).transpose()?;
  
         let mut encoder = self
             .device
@@ -153,46 +716,121 @@ impl Gpu {
                 label: Some("Render Encoder"),
             });
 
+        // Shadow maps are drawn first so the main pass can sample them
+        for (index, (layer, view_projection)) in self.lights.shadow_maps().enumerate() {
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Shadow Pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: layer,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store
+                        }),
+                        stencil_ops: None
+                    }),
+                    occlusion_query_set: None,
+                    timestamp_writes: self.profiler
+                        .pass_timestamp_writes(&format!("Shadow pass {index}")),
+                });
+
+                let mut context = RenderContext {
+                    uploads: &mut self.uploads,
+                    profiler: &mut self.profiler,
+                    shadow: Some(ShadowPass::new(&self.shadow_caster, view_projection)),
+                };
+                set_render_pass(&mut render_pass, &mut context);
+            }
+
+            self.profiler.end_pass();
+        }
+
         {
-            let bg_rgb = [60, 136, 151]
-                .map(|x| x as f64 / 255.0) // Normalize
-                .map(|x| x.powf(2.2)); // Convert to sRGB
-            
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: self.msaa.as_ref().unwrap_or(view),
                     depth_slice: None,
-                    resolve_target: None,
+                    resolve_target: self.msaa.as_ref().map(|_| view),
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: bg_rgb[0],
-                            g: bg_rgb[1],
-                            b: bg_rgb[2],
-                            a: 1.0,
-                        }),
+                        load: self.clear.color_load_op(self.config.format),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: self.clear.depth_load_op(),
                         store: wgpu::StoreOp::Store
                     }),
                     stencil_ops: None
                 }),
                 occlusion_query_set: None,
-                timestamp_writes: None,
+                timestamp_writes: self.profiler.pass_timestamp_writes("Render pass"),
             });
 
-            set_render_pass(&mut render_pass);
+            if let Some(gradient) = &self.gradient {
+                gradient.draw(&mut render_pass);
+            }
+
+            // Shared by every lit material, which all expect it at group 1
+            render_pass.set_bind_group(1, self.lights.bind_group(), &[]);
+
+            let mut context = RenderContext {
+                uploads: &mut self.uploads,
+                profiler: &mut self.profiler,
+                shadow: None,
+            };
+            set_render_pass(&mut render_pass, &mut context);
+        }
+
+        self.profiler.end_pass();
+
+        if let Some(blit) = &blit {
+            blit.draw(&mut encoder, &frame.view);
+        }
+
+        self.profiler.end_frame(&self.device, &mut encoder);
+
+        let readback = if self.capture_requested {
+            self.capture_requested = false;
+            let texture = capture_texture.as_ref().unwrap_or(&frame.texture);
+            Some(PendingReadback::copy_texture(&self.device, &mut encoder, texture)?)
+        } else {
+            None
+        };
+
+        // Uploads have to land before the pass that reads them
+        let uploads = self.uploads.finish();
+        self.queue.submit(uploads.into_iter().chain(std::iter::once(encoder.finish())));
+        self.uploads.recall();
+        self.profiler.after_submit();
+
+        frame.present();
+
+        if let Some(readback) = readback {
+            self.capture = Some(readback.read(&self.device)?);
         }
 
+        self.target.request_redraw();
+        Ok(status)
+    }
+
+    /// Copies the last rendered offscreen frame back to the CPU.
+    pub fn read_pixels(&self) -> Result<image::RgbaImage> {
+        let texture = self.target.offscreen_texture()
+            .ok_or_else(|| anyhow!("Pixel readback requires an offscreen render target"))?;
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+
+        let readback = PendingReadback::copy_texture(&self.device, &mut encoder, texture)?;
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
 
-        self.window.request_redraw();
-        Ok(())
+        readback.read(&self.device)
     }
 }
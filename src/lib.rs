@@ -0,0 +1,24 @@
+pub mod background;
+pub mod camera;
+pub mod color;
+pub mod config;
+pub mod controller;
+pub mod gpu;
+pub mod renderer;
+pub mod object;
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod profiler;
+pub mod readback;
+pub mod scene;
+pub mod light;
+pub mod shadow;
+pub mod material;
+pub mod pbr;
+pub mod mesh;
+pub mod data;
+pub mod error;
+pub mod target;
+pub mod texture;
+pub mod transform;
+pub mod upload;
@@ -0,0 +1,339 @@
+use std::{borrow::Cow, mem::size_of};
+use bytemuck::NoUninit;
+use glam::{Mat4, Vec3};
+use crate::{
+    camera::Camera,
+    pipeline::Shader,
+    shadow::{ShadowMaps, ShadowSettings},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away, like the sun. `direction` is where the light
+    /// travels, not where it comes from.
+    Directional { direction: Vec3 },
+    Point { position: Vec3, range: f32 },
+    /// Cone of light, fully lit within `inner_angle` of `direction` and
+    /// fading out towards `outer_angle`. Angles are in radians from the axis.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Renders shadow maps for this light. Only directional and spot
+    /// lights support shadows.
+    pub casts_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional { direction },
+            color,
+            intensity,
+            casts_shadows: false,
+        }
+    }
+
+    pub fn point(position: Vec3, range: f32, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Point { position, range },
+            color,
+            intensity,
+            casts_shadows: false,
+        }
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, range: f32,
+                inner_angle: f32, outer_angle: f32,
+                color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Spot { position, direction, range, inner_angle, outer_angle },
+            color,
+            intensity,
+            casts_shadows: false,
+        }
+    }
+
+    pub fn with_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    fn to_gpu(self) -> GpuLight {
+        let mut light = GpuLight {
+            color: self.color.into(),
+            intensity: self.intensity,
+            shadow_map: -1,
+            ..Default::default()
+        };
+
+        match self.kind {
+            LightKind::Directional { direction } => {
+                light.kind = 0;
+                light.direction = direction.normalize_or(Vec3::NEG_Y).into();
+            }
+            LightKind::Point { position, range } => {
+                light.kind = 1;
+                light.position = position.into();
+                light.range = range;
+            }
+            LightKind::Spot { position, direction, range, inner_angle, outer_angle } => {
+                light.kind = 2;
+                light.position = position.into();
+                light.direction = direction.normalize_or(Vec3::NEG_Y).into();
+                light.range = range;
+                light.cos_inner = inner_angle.cos();
+                // An outer cone inside the inner one would divide by zero
+                light.cos_outer = outer_angle.max(inner_angle).cos().min(light.cos_inner - 0.0001);
+            }
+        }
+
+        light
+    }
+}
+
+// Matches `Light` in lights.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Default, NoUninit)]
+struct GpuLight {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    intensity: f32,
+    cos_inner: f32,
+    cos_outer: f32,
+    /// First shadow map of the light, -1 without shadows.
+    shadow_map: i32,
+    _padding: f32,
+}
+
+// Header of `Lights` in lights.wgsl, followed by the shadow matrices and
+// the light array
+#[repr(C)]
+#[derive(Copy, Clone, NoUninit)]
+struct LightsHeader {
+    ambient: [f32; 3],
+    count: u32,
+    camera_position: [f32; 3],
+    cascade_count: u32,
+    camera_forward: [f32; 3],
+    normal_bias: f32,
+    cascade_splits: [f32; 4],
+}
+
+/// Prepends the light definitions bound at group 1 to a material shader.
+pub fn lit_shader(label: &'static str, source: &str, lights: &LightBuffer) -> Shader {
+    Shader {
+        label,
+        source: Cow::Owned(format!(
+            "const MAX_LIGHTS: u32 = {}u;\nconst MAX_SHADOW_MAPS: u32 = {}u;\n{}\n{source}",
+            lights.max_lights.max(1),
+            lights.max_shadow_maps(),
+            include_str!("shaders/lights.wgsl")
+        )),
+    }
+}
+
+/// Uniform buffer holding the scene's lights together with their shadow
+/// maps, bound at group 1 for every lit material.
+pub struct LightBuffer {
+    buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    max_lights: u32,
+    shadows: ShadowSettings,
+    shadow_maps: ShadowMaps,
+    // View-projection matrices of the shadow maps used this frame
+    shadow_matrices: Vec<Mat4>,
+    warned_overflow: bool,
+    warned_shadow_overflow: bool,
+}
+
+impl LightBuffer {
+    pub(crate) fn buffer_size(max_lights: u32, shadows: &ShadowSettings) -> u64 {
+        (size_of::<LightsHeader>()
+         + size_of::<Mat4>() * shadows.max_maps.max(1) as usize
+         + size_of::<GpuLight>() * max_lights.max(1) as usize) as u64
+    }
+
+    pub(crate) fn new(device: &wgpu::Device, max_lights: u32, shadows: ShadowSettings) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: "Light buffer".into(),
+            size: Self::buffer_size(max_lights, &shadows),
+            mapped_at_creation: false,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: "Light bind group layout".into(),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None
+                },
+            ]
+        });
+
+        let shadow_maps = ShadowMaps::new(device, &shadows);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Light bind group".into(),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding()
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(shadow_maps.array_view())
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(shadow_maps.sampler())
+                },
+            ]
+        });
+
+        Self {
+            buffer,
+            layout,
+            bind_group,
+            max_lights,
+            shadows,
+            shadow_maps,
+            shadow_matrices: Vec::new(),
+            warned_overflow: false,
+            warned_shadow_overflow: false,
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn max_lights(&self) -> u32 {
+        self.max_lights
+    }
+
+    pub fn max_shadow_maps(&self) -> u32 {
+        self.shadows.max_maps.max(1)
+    }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings {
+        &self.shadows
+    }
+
+    /// Shadow maps to render this frame with their light view-projection
+    /// matrices.
+    pub(crate) fn shadow_maps(&self) -> impl Iterator<Item = (&wgpu::TextureView, Mat4)> {
+        self.shadow_matrices
+            .iter()
+            .enumerate()
+            .map(|(index, &matrix)| (self.shadow_maps.layer(index), matrix))
+    }
+
+    pub(crate) fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Uploads `lights`, dropping any beyond `max_lights`, and places the
+    /// shadow maps of shadow casting ones around `camera`.
+    pub(crate) fn write(&mut self, queue: &wgpu::Queue, ambient: Vec3, lights: &[Light],
+                        camera: &Camera) {
+        if lights.len() > self.max_lights as usize && !self.warned_overflow {
+            log::warn!("Scene has {} lights, only the first {} are used",
+                       lights.len(), self.max_lights);
+            self.warned_overflow = true;
+        }
+
+        let splits = self.shadows.cascade_splits(camera);
+        self.shadow_matrices.clear();
+
+        let lights: Vec<_> = lights
+            .iter()
+            .take(self.max_lights as usize)
+            .map(|light| {
+                let mut gpu_light = light.to_gpu();
+                if !light.casts_shadows {
+                    return gpu_light;
+                }
+
+                // Point lights have no shadow maps to point at
+                let matrices = self.shadows.light_matrices(light, camera, &splits);
+                if matrices.is_empty() {
+                    return gpu_light;
+                }
+
+                let first = self.shadow_matrices.len();
+                if first + matrices.len() <= self.shadows.max_maps as usize {
+                    gpu_light.shadow_map = first as i32;
+                    self.shadow_matrices.extend(matrices);
+                } else if !self.warned_shadow_overflow {
+                    log::warn!("Out of shadow maps, some lights are drawn without shadows");
+                    self.warned_shadow_overflow = true;
+                }
+
+                gpu_light
+            })
+            .collect();
+
+        let header = LightsHeader {
+            ambient: ambient.into(),
+            count: lights.len() as u32,
+            camera_position: camera.position.into(),
+            cascade_count: self.shadows.cascade_count(),
+            camera_forward: camera.forward().into(),
+            normal_bias: self.shadows.normal_bias,
+            cascade_splits: splits,
+        };
+
+        let matrices_offset = size_of::<LightsHeader>() as u64;
+        let lights_offset = matrices_offset
+            + (size_of::<Mat4>() * self.max_shadow_maps() as usize) as u64;
+
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&header));
+        if !self.shadow_matrices.is_empty() {
+            queue.write_buffer(&self.buffer, matrices_offset,
+                               bytemuck::cast_slice(&self.shadow_matrices));
+        }
+        if !lights.is_empty() {
+            queue.write_buffer(&self.buffer, lights_offset, bytemuck::cast_slice(&lights));
+        }
+    }
+}
@@ -1,26 +1,27 @@
-mod gpu;
-mod renderer;
-mod object;
-mod material;
-mod mesh;
-mod data;
-
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
-    event::WindowEvent,
+    event::{ElementState, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
     window::Window,
 };
 
-use crate::{
-    gpu::Gpu,
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use webgpu::{
+    config::GpuConfig,
+    controller::{FlyController, OrbitController},
+    gpu::{Gpu, RenderStatus},
     renderer::Renderer,
 };
 
 #[derive(Default)]
 struct App {
-    renderer: Option<Renderer>
+    renderer: Option<Renderer>,
+    flying: bool,
+    assets: PathBuf,
 }
 
 impl ApplicationHandler for App {
@@ -28,12 +29,16 @@ impl ApplicationHandler for App {
         let size = PhysicalSize::new(640, 480);
 
         let attrs = Window::default_attributes()
-            .with_inner_size(size.clone())
-            .with_resizable(false);
+            .with_inner_size(size);
 
         let window = event_loop.create_window(attrs).unwrap();
-        let gpu = pollster::block_on(Gpu::new(window, size)).unwrap();
-        self.renderer = Some(Renderer::new(gpu));
+        let settings = GpuConfig::default()
+            .with_sample_count(4)
+            .with_env_overrides();
+        let gpu = pollster::block_on(Gpu::new(window, size, settings)).unwrap();
+        let mut renderer = Renderer::new(gpu, &self.assets).unwrap();
+        renderer.set_controller(Some(Box::new(OrbitController::default())));
+        self.renderer = Some(renderer);
     }
 
     fn window_event(
@@ -54,18 +59,117 @@ impl ApplicationHandler for App {
                 event_loop.exit();
             }
             WindowEvent::Resized(size) => {
-                renderer.resize(size);
+                if let Err(err) = renderer.resize(size) {
+                    log::error!("Resizing failed: {err:#}");
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::F12),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                let path = screenshot_path();
+                match renderer.screenshot(&path) {
+                    Ok(()) => println!("Saved screenshot to {}", path.display()),
+                    Err(err) => log::error!("Screenshot failed: {err:#}"),
+                }
             }
-            WindowEvent::RedrawRequested => {
-                renderer.render().unwrap();
+            // Switches between orbiting the scene and flying through it
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    physical_key: PhysicalKey::Code(KeyCode::Tab),
+                    state: ElementState::Pressed,
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.flying = !self.flying;
+                if self.flying {
+                    renderer.set_controller(Some(Box::new(FlyController::default())));
+                } else {
+                    renderer.set_controller(Some(Box::new(OrbitController::default())));
+                }
+            }
+            WindowEvent::RedrawRequested => match renderer.render() {
+                Ok(RenderStatus::Presented) => (),
+                Ok(status) => log::debug!("Frame not presented normally: {status:?}"),
+                Err(err) => {
+                    log::error!("Rendering failed: {err:#}");
+                    event_loop.exit();
+                }
+            },
+            event => {
+                renderer.window_event(&event);
             }
-            _ => (),
         }
     }
 }
 
+fn screenshot_path() -> PathBuf {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    PathBuf::from(format!("screenshot-{timestamp}.png"))
+}
+
+// Removes `name` and the value after it from `args`
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.remove(index);
+
+    (index < args.len()).then(|| args.remove(index))
+}
+
+// `--assets <dir>`, then `$WEBGPU_ASSETS`, then a `res` directory next to
+// the executable, then the one in a source checkout run from its root
+fn assets_dir(arg: Option<String>) -> PathBuf {
+    if let Some(dir) = arg.or_else(|| std::env::var("WEBGPU_ASSETS").ok()) {
+        return PathBuf::from(dir);
+    }
+
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("res")))
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(|| PathBuf::from("src/res"))
+}
+
+fn render_headless(path: &Path, assets: &Path) -> Result<()> {
+    let size = PhysicalSize::new(640, 480);
+    let settings = GpuConfig::default()
+        .with_sample_count(4)
+        .with_env_overrides();
+    let gpu = pollster::block_on(Gpu::new_headless(size, settings))?;
+    let mut renderer = Renderer::new(gpu, assets)?;
+
+    renderer.render()?;
+    renderer.read_pixels()?.save(path)?;
+    println!("Saved headless render to {}", path.display());
+
+    Ok(())
+}
+
 fn main() {
+    env_logger::init();
+
+    let mut args: Vec<_> = std::env::args().skip(1).collect();
+    let assets = assets_dir(take_option(&mut args, "--assets"));
+
+    let mut args = args.into_iter();
+    if let Some("--headless") = args.next().as_deref() {
+        let path = args.next().unwrap_or_else(|| "render.png".into());
+        render_headless(Path::new(&path), &assets).unwrap();
+        return;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
-    event_loop.run_app(&mut App::default()).unwrap();
+    event_loop.run_app(&mut App { assets, ..App::default() }).unwrap();
 }
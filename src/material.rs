@@ -1,19 +1,76 @@
-use std::{default::Default, mem::size_of, num::NonZero, path::Path};
-use crate::{data::Vertex, gpu::Gpu};
+use std::{default::Default, mem::size_of, num::NonZero, path::PathBuf};
+use crate::{
+    camera::Camera,
+    data::Vertex,
+    error::GpuError,
+    gpu::Gpu,
+    upload::FrameUploads,
+    light,
+    pipeline::{PipelineKey, Shader, VertexLayout},
+    texture::BuiltinTexture,
+};
+use anyhow::Result;
 use bytemuck::NoUninit;
 use glam::{Mat4, Vec3};
-use wgpu::{Extent3d, TexelCopyBufferLayout};
 
-// TODO - refactor camera position out of this
 pub trait Material {
-    fn set_render_pass(&self, render_pass: &mut wgpu::RenderPass, queue: &wgpu::Queue, camera: Vec3);
+    /// Binds the material for the next draw. Returns `false` when its
+    /// uniforms couldn't be uploaded and the draw has to be skipped.
+    fn set_render_pass(&self, render_pass: &mut wgpu::RenderPass,
+                       uploads: &mut FrameUploads) -> bool;
     // TODO: refactor transforms out of materials into a separate bind group
     // owned by Object
-    fn set_projection_xform(&mut self, transform: Mat4);
-    fn set_view_xform(&mut self, transform: Mat4);
+    fn set_camera(&mut self, camera: &Camera);
     fn set_model_xform(&mut self, transform: Mat4);
+    /// Recreates every GPU resource after the device was lost.
+    fn rebuild(&mut self, gpu: &Gpu) -> Result<()>;
 }
 
+/// Texture maps of a `SimpleMaterial`, named after their MTL statements.
+/// Missing maps leave the matching factor in `SimpleFactors` on its own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimpleTextures {
+    /// `map_Kd`, sRGB.
+    pub diffuse: Option<PathBuf>,
+    /// `norm` or `map_Bump`, tangent space normals.
+    pub normal: Option<PathBuf>,
+    /// `map_Ks`, sRGB.
+    pub specular: Option<PathBuf>,
+    /// `map_Ns`, scales the shininess by its red channel.
+    pub shininess: Option<PathBuf>,
+    /// `map_d`, scales the opacity by its red channel.
+    pub dissolve: Option<PathBuf>,
+}
+
+/// Colors and exponents of a `SimpleMaterial`, following the MTL
+/// parameters of the same names. Colors are linear RGB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimpleFactors {
+    /// `Ka`, reflects the scene's ambient light.
+    pub ambient: Vec3,
+    /// `Kd`
+    pub diffuse: Vec3,
+    /// `Ks`
+    pub specular: Vec3,
+    /// `Ns`, the Blinn-Phong exponent.
+    pub shininess: f32,
+    /// `d`, opacity from 0 (invisible) to 1.
+    pub dissolve: f32,
+}
+
+impl Default for SimpleFactors {
+    fn default() -> Self {
+        Self {
+            ambient: Vec3::ONE,
+            diffuse: Vec3::ONE,
+            specular: Vec3::splat(0.4),
+            shininess: 32.0,
+            dissolve: 1.0,
+        }
+    }
+}
+
+// Matches `BindingInput` in simple.wgsl
 #[repr(C, packed)]
 #[derive(Copy, Clone, NoUninit)]
 struct UniformData {
@@ -23,85 +80,198 @@ struct UniformData {
     pub normal: Mat4,
     pub camera_pos: Vec3,
     pub time: f32,
+    pub ambient: Vec3,
+    pub shininess: f32,
+    pub diffuse: Vec3,
+    pub dissolve: f32,
+    pub specular: Vec3,
+    pub _padding: f32,
 }
 
+// Texture slots in binding order: what each one falls back to and whether
+// it holds sRGB color
+const MAPS: [(BuiltinTexture, bool); 5] = [
+    (BuiltinTexture::White, true),       // diffuse
+    (BuiltinTexture::FlatNormal, false), // normal
+    (BuiltinTexture::White, true),       // specular
+    (BuiltinTexture::White, false),      // shininess
+    (BuiltinTexture::White, false),      // dissolve
+];
+
+/// Pipeline drawing `Vertex` meshes with a lit material shader, which
+/// takes the material at group 0 and the lights at group 1. Transparent
+/// pipelines blend by alpha and leave the depth buffer alone.
+pub(crate) fn lit_pipeline_key(gpu: &Gpu, shader: Shader,
+                               bind_group_layout: wgpu::BindGroupLayout,
+                               transparent: bool) -> PipelineKey {
+    PipelineKey {
+        shader,
+        vertex_entry: "vs_main",
+        fragment_entry: Some("fs_main"),
+        bind_group_layouts: vec![bind_group_layout, gpu.lights().layout().clone()],
+        vertex_layouts: vec![
+            VertexLayout {
+                array_stride: size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: wgpu::vertex_attr_array![
+                    0 => Float32x3,
+                    1 => Float32x3,
+                    2 => Float32x3,
+                    3 => Float32x3,
+                    4 => Float32x2
+                ].to_vec()
+            }
+        ],
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None, //Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false
+        },
+        targets: vec![Some(wgpu::ColorTargetState {
+            format: gpu.config.format,
+            blend: Some(if transparent {
+                wgpu::BlendState::ALPHA_BLENDING
+            } else {
+                wgpu::BlendState::REPLACE
+            }),
+            write_mask: wgpu::ColorWrites::ALL
+        })],
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Gpu::DEPTH_FORMAT,
+            depth_write_enabled: !transparent,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default()
+        }),
+        sample_count: gpu.sample_count(),
+    }
+}
+
+/// Bind group layout shared by the textured materials: a dynamic offset
+/// uniform of `uniform_size` bytes at binding 0, `maps` 2D textures after
+/// it and a filtering sampler last.
+pub(crate) fn textured_layout(uniform_size: u64, maps: usize) -> Vec<wgpu::BindGroupLayoutEntry> {
+    let uniforms = wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: NonZero::new(uniform_size)
+        },
+        count: None
+    };
+
+    let textures = (1..=maps as u32).map(|binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float {
+                filterable: true
+            },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false
+        },
+        count: None
+    });
+
+    let sampler = wgpu::BindGroupLayoutEntry {
+        binding: maps as u32 + 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None
+    };
+
+    std::iter::once(uniforms).chain(textures).chain(std::iter::once(sampler)).collect()
+}
+
+/// Fetches the texture of every map from the registry and binds them
+/// following `textured_layout`, which is returned along with the group.
+/// Each map comes with what it falls back to and whether it's sRGB.
+pub(crate) fn textured_bind_group(gpu: &Gpu, label: &str, uniform_size: u64,
+                                  maps: &[(&Option<PathBuf>, (BuiltinTexture, bool))],
+                                  sampler: &wgpu::SamplerDescriptor)
+                                  -> Result<(wgpu::BindGroup, wgpu::BindGroupLayout), GpuError> {
+    let views = gpu.scoped(&format!("{label} textures"), |device| {
+        maps.iter()
+            .map(|&(path, (fallback, srgb))| {
+                gpu.textures().load_or(device, &gpu.queue, path.as_deref(), srgb, fallback)
+            })
+            .collect::<Vec<_>>()
+    })?;
+
+    let layout = gpu.pipelines().bind_group_layout(
+        &gpu.device,
+        &format!("{label} bind group layout"),
+        &textured_layout(uniform_size, maps.len())
+    )?;
+
+    let group_label = format!("{label} bind group");
+    let bind_group = gpu.scoped(&group_label, |device| {
+        let sampler = device.create_sampler(sampler);
+
+        let uniforms = wgpu::BindGroupEntry {
+            binding: 0,
+            resource: gpu.uploads().uniform_binding(uniform_size)
+        };
+        let textures = views.iter().zip(1..).map(|(view, binding)| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view)
+        });
+        let sampler = wgpu::BindGroupEntry {
+            binding: maps.len() as u32 + 1,
+            resource: wgpu::BindingResource::Sampler(&sampler)
+        };
+
+        let entries: Vec<_> = std::iter::once(uniforms)
+            .chain(textures)
+            .chain(std::iter::once(sampler))
+            .collect();
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&group_label),
+            layout: &layout,
+            entries: &entries
+        })
+    })?;
+
+    Ok((bind_group, layout))
+}
+
+/// Blinn-Phong material driven by the parameters of an MTL file.
+/// Transparent materials are blended in draw order, without sorting.
 pub struct SimpleMaterial {
-    pipeline: wgpu::RenderPipeline,
+    pub factors: SimpleFactors,
+    // Shared with every other material using the same pipeline keys
+    opaque_pipeline: wgpu::RenderPipeline,
+    transparent_pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
-    uniform_buffer: wgpu::Buffer,
     start_time: std::time::Instant,
     // TODO - refactor this
     projection: Mat4,
     view: Mat4,
+    camera_pos: Vec3,
     model: Mat4,
-    texture: wgpu::Texture,
+    // Kept around to fetch the textures again after device loss
+    textures: SimpleTextures,
 }
 
 impl SimpleMaterial {
-    // TODO - refactor texture inputs into another struct
-    fn setup_bind_group(device: &wgpu::Device, uniform_buffer: &wgpu::Buffer,
-                        texture: &wgpu::Texture, normal_map: &wgpu::Texture)
-                        -> (wgpu::BindGroup, wgpu::PipelineLayout) {
-        let bind_group_layout_descriptor = wgpu::BindGroupLayoutDescriptor {
-            label: "Simple material bind group layout".into(),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    },
-                    count: None
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float {
-                            filterable: true
-                        },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false
-                    },
-                    count: None
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float {
-                            filterable: true
-                        },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false
-                    },
-                    count: None
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None
-                }
-            ]
-        };
-
-        let bind_group_layout = device.create_bind_group_layout(&bind_group_layout_descriptor);
+    fn make_gpu_resources(gpu: &Gpu, textures: &SimpleTextures)
+                          -> Result<(wgpu::BindGroup, wgpu::RenderPipeline, wgpu::RenderPipeline)> {
+        let paths = [
+            &textures.diffuse,
+            &textures.normal,
+            &textures.specular,
+            &textures.shininess,
+            &textures.dissolve,
+        ];
 
-        let pipeline_descriptor = wgpu::PipelineLayoutDescriptor {
-            label: "Uniform buffer layout".into(),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[]
-        };
-
-        let pipeline_layout = device.create_pipeline_layout(&pipeline_descriptor);
-
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let normal_view = normal_map.create_view(&wgpu::TextureViewDescriptor::default());
-        
-        let sampler_descriptor = wgpu::SamplerDescriptor {
+        let sampler = wgpu::SamplerDescriptor {
             label: "Simple texture sampler".into(),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -111,202 +281,54 @@ impl SimpleMaterial {
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         };
-        let sampler = device.create_sampler(&sampler_descriptor);
-
-        let bind_group_descriptor = wgpu::BindGroupDescriptor {
-            label: "Bind group".into(),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &uniform_buffer,
-                        offset: 0,
-                        size: NonZero::new(size_of::<UniformData>() as u64)
-                    })
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&texture_view)
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_view)
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&sampler)
-                }
-            ]
-        };
-
-        let bind_group = device.create_bind_group(&bind_group_descriptor);
-
-        (bind_group, pipeline_layout)
-    }
- 
-    fn make_pipeline(device: &wgpu::Device,
-                     config: &wgpu::SurfaceConfiguration,
-                     pipeline_layout: &wgpu::PipelineLayout) -> wgpu::RenderPipeline {
-        let shader_module = device.create_shader_module(
-            wgpu::include_wgsl!("shaders/simple.wgsl")
+        let (bind_group, layout) = textured_bind_group(
+            gpu,
+            "Simple material",
+            size_of::<UniformData>() as u64,
+            &paths.into_iter().zip(MAPS).collect::<Vec<_>>(),
+            &sampler
+        )?;
+        let shader = light::lit_shader(
+            "shaders/simple.wgsl",
+            include_str!("shaders/simple.wgsl"),
+            gpu.lights()
         );
-
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Triangle render"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader_module,
-                entry_point: Some("vs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                buffers: &[
-                    wgpu::VertexBufferLayout {
-                        array_stride: size_of::<Vertex>() as u64,
-                        step_mode: wgpu::VertexStepMode::Vertex,
-                        attributes: &wgpu::vertex_attr_array![
-                            0 => Float32x3,
-                            1 => Float32x3,
-                            2 => Float32x3,
-                            3 => Float32x3,
-                            4 => Float32x2
-                        ]
-                    }
-                ]
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None, //Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: Some("fs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL
-                })],
-            }),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                // TODO - grab this info from outside
-                format: wgpu::TextureFormat::Depth24Plus,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default()
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0u64,
-                alpha_to_coverage_enabled: false
-            },
-            multiview: None,
-            cache: None
-        })
-    }
-
-    fn make_uniform_buffer(device: &wgpu::Device) -> wgpu::Buffer {
-        let descriptor = wgpu::BufferDescriptor {
-            label: "Uniform buffer".into(),
-            // TODO: round the size to a multiple of 4 f32 values
-            size: size_of::<UniformData>() as u64,
-            mapped_at_creation: false,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM
+        let pipeline = |transparent| {
+            let key = lit_pipeline_key(gpu, shader.clone(), layout.clone(), transparent);
+            gpu.pipelines().render_pipeline(&gpu.device, gpu.pipeline_cache(), &key)
         };
+        let (opaque, transparent) = (pipeline(false)?, pipeline(true)?);
 
-        device.create_buffer(&descriptor)
+        Ok((bind_group, opaque, transparent))
     }
 
-    fn make_texture(device: &wgpu::Device, queue: &wgpu::Queue,
-                    path: &Path, format: wgpu::TextureFormat) -> wgpu::Texture {
-        let texture_bytes = std::fs::read(path).unwrap();
-        let texture_rgba = image::load_from_memory(&texture_bytes).unwrap()
-            .to_rgba8();
-        let (tex_width, tex_height) = texture_rgba.dimensions();
-        let extent = Extent3d {
-            width: tex_width,
-            height: tex_height,
-            depth_or_array_layers: 1
-        };
-        
-        let descriptor = wgpu::TextureDescriptor {
-            label: "Simple texture".into(),
-            dimension: wgpu::TextureDimension::D2,
-            size: extent,
-            format,
-            sample_count: 1,
-            mip_level_count: 1,
-            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[]
-        };
-
-        let texture = device.create_texture(&descriptor);
-
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All
-            },
-            &texture_rgba,
-            TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(tex_width * 4),
-                rows_per_image: Some(tex_height)
-            },
-            extent
-        );
-
-        texture
-    }
-    
-    pub fn new(gpu: &Gpu, texture_path: &Path, normal_path: &Path) -> Self {
-        let uniform_buffer = Self::make_uniform_buffer(&gpu.device);
-        let texture = Self::make_texture(
-            &gpu.device,
-            &gpu.queue,
-            texture_path,
-            wgpu::TextureFormat::Rgba8UnormSrgb
-        );
-        let normal_map = Self::make_texture(
-            &gpu.device,
-            &gpu.queue,
-            normal_path,
-            wgpu::TextureFormat::Rgba8Unorm
-        );
-        let (bind_group, pipeline_layout) = Self::setup_bind_group(
-            &gpu.device,
-            &uniform_buffer,
-            &texture,
-            &normal_map
-        );
-        let pipeline = Self::make_pipeline(&gpu.device, &gpu.config, &pipeline_layout);
+    pub fn new(gpu: &Gpu, textures: &SimpleTextures, factors: SimpleFactors) -> Result<Self> {
+        let (bind_group, opaque_pipeline, transparent_pipeline) =
+            Self::make_gpu_resources(gpu, textures)?;
         let start_time = std::time::Instant::now();
 
-        Self {
+        Ok(Self {
+            factors,
             bind_group,
-            uniform_buffer,
-            pipeline,
+            opaque_pipeline,
+            transparent_pipeline,
             start_time,
             projection: Mat4::IDENTITY,
             view: Mat4::IDENTITY,
+            camera_pos: Vec3::ZERO,
             model: Mat4::IDENTITY,
-            texture,
-        }
+            textures: textures.clone(),
+        })
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.factors.dissolve < 1.0 || self.textures.dissolve.is_some()
     }
 }
 
 impl Material for SimpleMaterial {
-    fn set_render_pass(&self, render_pass: &mut wgpu::RenderPass, queue: &wgpu::Queue, camera: Vec3) {
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, &self.bind_group, &[]);
-
+    fn set_render_pass(&self, render_pass: &mut wgpu::RenderPass,
+                       uploads: &mut FrameUploads) -> bool {
         let time = std::time::Instant::now()
             .duration_since(self.start_time)
             .as_secs_f32();
@@ -316,22 +338,44 @@ impl Material for SimpleMaterial {
             view: self.view,
             model: self.model,
             normal: self.model.inverse().transpose(),
-            camera_pos: camera,
+            camera_pos: self.camera_pos,
             time,
+            ambient: self.factors.ambient,
+            shininess: self.factors.shininess,
+            diffuse: self.factors.diffuse,
+            dissolve: self.factors.dissolve.clamp(0.0, 1.0),
+            specular: self.factors.specular,
+            _padding: 0.0,
         };
 
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform_data));
-    }
+        let Some(offset) = uploads.push_uniform(&uniform_data) else {
+            return false;
+        };
 
-    fn set_projection_xform(&mut self, transform: Mat4) {
-        self.projection = transform;
+        if self.is_transparent() {
+            render_pass.set_pipeline(&self.transparent_pipeline);
+        } else {
+            render_pass.set_pipeline(&self.opaque_pipeline);
+        }
+        render_pass.set_bind_group(0, &self.bind_group, &[offset]);
+
+        true
     }
 
-    fn set_view_xform(&mut self, transform: Mat4) {
-        self.view = transform;
+    fn set_camera(&mut self, camera: &Camera) {
+        self.projection = camera.projection_matrix();
+        self.view = camera.view_matrix();
+        self.camera_pos = camera.position;
     }
 
     fn set_model_xform(&mut self, transform: Mat4) {
         self.model = transform;
     }
+
+    fn rebuild(&mut self, gpu: &Gpu) -> Result<()> {
+        (self.bind_group, self.opaque_pipeline, self.transparent_pipeline) =
+            Self::make_gpu_resources(gpu, &self.textures)?;
+
+        Ok(())
+    }
 }
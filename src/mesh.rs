@@ -1,5 +1,5 @@
-use std::mem::size_of;
-use crate::{Gpu, data::Vertex};
+use std::mem::size_of_val;
+use crate::{gpu::Gpu, data::Vertex, error::GpuError};
 
 pub struct Mesh {
     vertices: Vec<Vertex>,
@@ -12,7 +12,7 @@ impl Mesh {
     fn make_vertex_buffer(device: &wgpu::Device, vtx: &[Vertex]) -> wgpu::Buffer {
         let descriptor = wgpu::BufferDescriptor {
             label: "Vertex buffer".into(),
-            size: (vtx.len() * size_of::<Vertex>()) as u64,
+            size: size_of_val(vtx) as u64,
             mapped_at_creation: false,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX
         };
@@ -23,7 +23,7 @@ impl Mesh {
     fn make_index_buffer(device: &wgpu::Device, idx: &[u32]) -> wgpu::Buffer {
         let descriptor = wgpu::BufferDescriptor {
             label: "Index buffer".into(),
-            size: (idx.len() * size_of::<u32>()) as u64,
+            size: size_of_val(idx) as u64,
             mapped_at_creation: false,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::INDEX
         };
@@ -31,18 +31,35 @@ impl Mesh {
         device.create_buffer(&descriptor)
     }
 
-    pub fn new(gpu: &Gpu, vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
-        let vertex_buffer = Self::make_vertex_buffer(&gpu.device, &vertices);
-        gpu.queue.write_buffer(&vertex_buffer, 0, &bytemuck::cast_slice(&vertices));
-        let index_buffer = Self::make_index_buffer(&gpu.device, &indices);
-        gpu.queue.write_buffer(&index_buffer, 0, &bytemuck::cast_slice(&indices));
+    fn make_buffers(gpu: &Gpu, vertices: &[Vertex], indices: &[u32])
+                    -> Result<(wgpu::Buffer, wgpu::Buffer), GpuError> {
+        gpu.scoped("Mesh buffers", |device| {
+            let vertex_buffer = Self::make_vertex_buffer(device, vertices);
+            gpu.queue.write_buffer(&vertex_buffer, 0, bytemuck::cast_slice(vertices));
+            let index_buffer = Self::make_index_buffer(device, indices);
+            gpu.queue.write_buffer(&index_buffer, 0, bytemuck::cast_slice(indices));
+
+            (vertex_buffer, index_buffer)
+        })
+    }
+
+    pub fn new(gpu: &Gpu, vertices: Vec<Vertex>, indices: Vec<u32>) -> Result<Self, GpuError> {
+        let (vertex_buffer, index_buffer) = Self::make_buffers(gpu, &vertices, &indices)?;
         
-        Self {
+        Ok(Self {
             vertex_buffer,
             index_buffer,
             vertices,
             indices,
-        }
+        })
+    }
+
+    /// Reuploads the retained vertex and index data to a new device.
+    pub fn rebuild(&mut self, gpu: &Gpu) -> Result<(), GpuError> {
+        (self.vertex_buffer, self.index_buffer) =
+            Self::make_buffers(gpu, &self.vertices, &self.indices)?;
+
+        Ok(())
     }
 
     pub fn set_render_pass(&self, render_pass: &mut wgpu::RenderPass) {
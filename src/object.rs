@@ -1,16 +1,13 @@
-use std::path::Path;
+use std::{cell::RefCell, fs::File, io::BufReader, path::{Path, PathBuf}};
 
-use glam::{Mat4, Vec3, Vec4};
-use tobj::LoadError;
+use glam::{Mat4, Vec3};
+use anyhow::{Context, Result, bail};
 
 use crate::{
-    data::Vertex, gpu::Gpu, material::{Material, SimpleMaterial}, mesh::Mesh
+    camera::Camera, data::Vertex, gpu::Gpu, material::{Material, SimpleFactors, SimpleMaterial, SimpleTextures}, mesh::Mesh,
+    shadow::ShadowPass, texture::TextureSearch, transform::Transform, upload::FrameUploads
 };
 
-// Refactor: Create a Camera entity that renders objects and provides
-// view and projection transforms. Create a Scene entity that stores
-// camera and objects
-
 // TODO - Remove this struct later
 struct Renderable {
     mesh: Mesh,
@@ -19,26 +16,29 @@ struct Renderable {
 
 pub struct Object {
     objs: Vec<Renderable>,
-    // Refactor this
-    projection_xform: Mat4,
-    view_xform: Mat4,
-    model_xform: Mat4,
+    pub transform: Transform,
 }
 
 impl Object {
-    fn make_projection_matrix() -> Mat4 {
-        let fov = 45.0 * std::f32::consts::PI / 180.0;
-        let ratio = 640.0/480.0;
-        let near = 0.01;
-        let far = 100.0;
-        
-        Mat4::perspective_lh(fov, ratio, near, far)
+    /// Builds an object out of meshes and the materials they're drawn with.
+    pub fn new(parts: Vec<(Mesh, Box<dyn Material>)>) -> Self {
+        Self {
+            objs: parts.into_iter()
+                .map(|(mesh, material)| Renderable { mesh, material })
+                .collect(),
+            transform: Transform::IDENTITY,
+        }
     }
 
-    const CAMERA_POS: Vec3 = Vec3::new(0.0, 0.0, 3.0); 
+    pub fn part_count(&self) -> usize {
+        self.objs.len()
+    }
 
-    fn make_view_matrix() -> Mat4 {
-        Mat4::from_translation(Self::CAMERA_POS)
+    /// Draws the `index`-th mesh with `material`, returning the one it had.
+    /// Panics if there's no such mesh.
+    pub fn set_material(&mut self, index: usize, material: Box<dyn Material>)
+                        -> Box<dyn Material> {
+        std::mem::replace(&mut self.objs[index].material, material)
     }
 
     fn fill_tangents(mut a: Vertex, mut b: Vertex, mut c: Vertex)
@@ -49,8 +49,8 @@ impl Object {
         let e_uv_b = glam::Vec2::from(b.uv) - glam::Vec2::from(a.uv);
         let e_uv_c = glam::Vec2::from(c.uv) - glam::Vec2::from(a.uv);
 
-        let mut t_vec = (e_pos_b * e_uv_c.y - e_pos_c * e_uv_b.y).normalize();
-        let mut b_vec = (e_pos_c * e_uv_b.x - e_pos_b * e_uv_c.x).normalize();
+        let t_vec = (e_pos_b * e_uv_c.y - e_pos_c * e_uv_b.y).normalize();
+        let b_vec = (e_pos_c * e_uv_b.x - e_pos_b * e_uv_c.x).normalize();
 
         for vtx in [&mut a, &mut b, &mut c] {
             vtx.tangent = t_vec.into();
@@ -60,108 +60,206 @@ impl Object {
         (a, b, c)
     }
 
-    pub fn load_obj(gpu: &Gpu, path: &Path) -> Result<Self, LoadError> {
-        let (models, materials) = tobj::load_obj(&path, &tobj::GPU_LOAD_OPTIONS)?;
-        let materials = materials.unwrap();
+    // Smooth normals weighted by the area of the faces around each vertex
+    fn fill_normals(vertices: &mut [Vertex], indices: &[u32]) {
+        let mut normals = vec![Vec3::ZERO; vertices.len()];
+        for tri in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(vertices[tri[i] as usize].pos));
+            let normal = (b - a).cross(c - a);
+            for &index in tri {
+                normals[index as usize] += normal;
+            }
+        }
+
+        for (vertex, normal) in vertices.iter_mut().zip(normals) {
+            vertex.normal = normal.normalize_or_zero().into();
+        }
+    }
+
+    // Drops options such as `-bm 0.5` in front of the file name
+    fn map_name(map: &str) -> &str {
+        if let Some("-bm") = map.split_whitespace().next() {
+            map.splitn(3, " ").last().unwrap_or(map)
+        }
+        else {
+            map
+        }
+    }
+
+    fn mtl_material(material: &tobj::Material, mtl_dir: &Path, search: &TextureSearch)
+                    -> (SimpleTextures, SimpleFactors) {
+        // Maps that can't be found are dropped so the slot gets its default
+        // texture instead of failing the model
+        let resolve = |map: &Option<String>| -> Option<PathBuf> {
+            let name = Self::map_name(map.as_deref()?);
+            search.resolve(mtl_dir, name)
+                .inspect_err(|err| log::warn!("Material {:?}: {err:#}", material.name))
+                .ok()
+        };
+
+        let textures = SimpleTextures {
+            diffuse: resolve(&material.diffuse_texture),
+            normal: resolve(&material.normal_texture),
+            specular: resolve(&material.specular_texture),
+            shininess: resolve(&material.shininess_texture),
+            dissolve: resolve(&material.dissolve_texture),
+        };
+
+        let defaults = SimpleFactors::default();
+        let diffuse = material.diffuse.map_or(defaults.diffuse, Vec3::from);
+
+        let factors = SimpleFactors {
+            // Files without `Ka` get ambient light in their diffuse color
+            ambient: material.ambient.map_or(diffuse, Vec3::from),
+            diffuse,
+            specular: material.specular.map_or(defaults.specular, Vec3::from),
+            shininess: material.shininess.unwrap_or(defaults.shininess),
+            dissolve: material.dissolve.unwrap_or(defaults.dissolve),
+        };
+
+        (textures, factors)
+    }
+
+    /// Loads an OBJ file and the materials of its MTL libraries, looking
+    /// textures up next to the MTL files.
+    pub fn load_obj(gpu: &Gpu, path: &Path) -> Result<Self> {
+        Self::load_obj_with(gpu, path, &TextureSearch::default())
+    }
+
+    pub fn load_obj_with(gpu: &Gpu, path: &Path, search: &TextureSearch) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let obj_dir = path.parent().unwrap_or(Path::new(""));
+
+        // Directory of the MTL file each material came from, in the order
+        // tobj appends them
+        let mtl_dirs = RefCell::new(Vec::new());
+        let (models, materials) = tobj::load_obj_buf(
+            &mut BufReader::new(file),
+            &tobj::GPU_LOAD_OPTIONS,
+            |mtl_path| {
+                let mtl_path = obj_dir.join(mtl_path);
+                let result = tobj::load_mtl(&mtl_path);
+                if let Ok((materials, _)) = &result {
+                    let dir = mtl_path.parent().unwrap_or(Path::new("")).to_path_buf();
+                    mtl_dirs.borrow_mut().extend(std::iter::repeat_n(dir, materials.len()));
+                }
+                result
+            }
+        ).with_context(|| format!("Failed to load {}", path.display()))?;
+        let materials = materials
+            .with_context(|| format!("Failed to load the materials of {}", path.display()))?;
+        let mtl_dirs = mtl_dirs.into_inner();
         let mut objs = Vec::<Renderable>::new();
  
         for model in models.iter() {
-            let mut vertices: Vec<_> = model.mesh.positions.chunks_exact(3)
-                .zip(model.mesh.normals.chunks_exact(3))
-                .zip(model.mesh.texcoords.chunks_exact(2))
-                .map(|((pos, normal), uv)| Vertex {
-                    pos: [pos[0], -pos[2], pos[1]],
-                    normal: [normal[0], normal[1], normal[2]],
-                    uv: [uv[0], 1.0 - uv[1]],
-                    ..Default::default()
+            let mesh = &model.mesh;
+
+            // Normals and texture coordinates are optional in OBJ files
+            let mut vertices: Vec<_> = mesh.positions.chunks_exact(3)
+                .enumerate()
+                .map(|(i, pos)| {
+                    let normal = mesh.normals.get(i * 3..i * 3 + 3).unwrap_or(&[0.0; 3]);
+                    let uv = mesh.texcoords
+                        .get(i * 2..i * 2 + 2)
+                        .map_or([0.0, 0.0], |uv| [uv[0], 1.0 - uv[1]]);
+
+                    Vertex {
+                        pos: [pos[0], -pos[2], pos[1]],
+                        normal: [normal[0], normal[1], normal[2]],
+                        uv,
+                        ..Default::default()
+                    }
                 })
                 .collect();
 
-            // TODO - refactor texture extraction code
+            if let Some(&index) = mesh.indices.iter().find(|&&index| index as usize >= vertices.len()) {
+                bail!("Model {:?} in {} refers to vertex {index} of {}",
+                      model.name, path.display(), vertices.len());
+            }
 
-            let texture_path = if let Some(id) = model.mesh.material_id
-                && let Some(diffuse) = &materials[id].diffuse_texture {
-                diffuse
+            if mesh.normals.is_empty() {
+                Self::fill_normals(&mut vertices, &mesh.indices);
             }
-            else {
-                &"src/res/star.png".into()
-            };
 
-            let normal_path = if let Some(id) = model.mesh.material_id
-                && let Some(normal) = &materials[id].normal_texture {
-                if let Some("-bm") = normal.split_whitespace().next() {
-                    normal.splitn(3, " ").last().unwrap()
-                }
-                else {
-                    normal
+            let material = match mesh.material_id {
+                Some(id) => {
+                    let (textures, factors) =
+                        Self::mtl_material(&materials[id], &mtl_dirs[id], search);
+                    SimpleMaterial::new(gpu, &textures, factors)?
                 }
-            }
-            else {
-                "src/res/star.png"
+                None => SimpleMaterial::new(gpu, &SimpleTextures::default(),
+                                            SimpleFactors::default())?,
             };
+            let material = Box::new(material);
 
-            let material = Box::new(SimpleMaterial::new(&gpu,
-                                                        &Path::new(texture_path),
-                                                        &Path::new(normal_path)));
-
-
-            for point_idx in model.mesh.indices.chunks_exact(3) {
-                let (a, b, c) = Self::fill_tangents(
-                    vertices[point_idx[0] as usize],
-                    vertices[point_idx[1] as usize],
-                    vertices[point_idx[2] as usize]
-                );
+            // Tangents follow the texture coordinates. Without them normal
+            // maps can't apply, any frame around the normal will do.
+            if mesh.texcoords.is_empty() {
+                for vertex in &mut vertices {
+                    if let Some(normal) = Vec3::from(vertex.normal).try_normalize() {
+                        let (tangent, bitangent) = normal.any_orthonormal_pair();
+                        vertex.tangent = tangent.into();
+                        vertex.bitangent = bitangent.into();
+                    }
+                }
+            } else {
+                for point_idx in mesh.indices.chunks_exact(3) {
+                    let (a, b, c) = Self::fill_tangents(
+                        vertices[point_idx[0] as usize],
+                        vertices[point_idx[1] as usize],
+                        vertices[point_idx[2] as usize]
+                    );
 
-                vertices[point_idx[0] as usize] = a;
-                vertices[point_idx[1] as usize] = b;
-                vertices[point_idx[2] as usize] = c;
+                    vertices[point_idx[0] as usize] = a;
+                    vertices[point_idx[1] as usize] = b;
+                    vertices[point_idx[2] as usize] = c;
+                }
             }
-            
-            let mesh = Mesh::new(gpu, vertices, model.mesh.indices.clone());
+
+            let mesh = Mesh::new(gpu, vertices, mesh.indices.clone())?;
 
             objs.push(Renderable { mesh, material });
         }
 
         Ok(Self {
             objs,
-            projection_xform: Self::make_projection_matrix(),
-            view_xform: Self::make_view_matrix(),
-            model_xform: Mat4::IDENTITY
+            transform: Transform::IDENTITY
         })
     }
 
-    pub fn set_render_pass(&mut self, render_pass: &mut wgpu::RenderPass, queue: &wgpu::Queue) {
+    /// Draws the object with its own transform applied on top of `world`.
+    pub fn set_render_pass(&mut self, render_pass: &mut wgpu::RenderPass,
+                           uploads: &mut FrameUploads, world: Mat4) {
         for Renderable { mesh, material } in &mut self.objs {
-            material.set_projection_xform(self.projection_xform);
-            material.set_view_xform(self.view_xform);
-            material.set_model_xform(self.model_xform);
-            material.set_render_pass(render_pass, queue, Self::CAMERA_POS);
-            
-            mesh.set_render_pass(render_pass);
+            material.set_model_xform(world * self.transform.to_matrix());
+            if material.set_render_pass(render_pass, uploads) {
+                mesh.set_render_pass(render_pass);
+            }
         }
     }
 
-    pub fn translate(&mut self, translation: Vec3) {
-        self.model_xform *= Mat4::from_translation(translation);
-    }
-
-    pub fn rotate_x(&mut self, rotation: f32) {
-        self.model_xform *= Mat4::from_rotation_x(rotation);
-    }
-
-    pub fn rotate_y(&mut self, rotation: f32) {
-        self.model_xform *= Mat4::from_rotation_y(rotation);
+    /// Draws the object's meshes into a shadow map.
+    pub fn set_shadow_pass(&self, render_pass: &mut wgpu::RenderPass,
+                           uploads: &mut FrameUploads, world: Mat4, shadow: &ShadowPass) {
+        let model = world * self.transform.to_matrix();
+        for Renderable { mesh, .. } in &self.objs {
+            shadow.draw(render_pass, uploads, model, mesh);
+        }
     }
 
-    pub fn rotate_z(&mut self, rotation: f32) {
-        self.model_xform *= Mat4::from_rotation_z(rotation);
-    }
+    pub fn rebuild(&mut self, gpu: &Gpu) -> Result<()> {
+        for Renderable { mesh, material } in &mut self.objs {
+            mesh.rebuild(gpu)?;
+            material.rebuild(gpu)?;
+        }
 
-    pub fn scale(&mut self, scale: Vec3) {
-        self.model_xform *= Mat4::from_scale(scale);
+        Ok(())
     }
 
-    pub fn reset(&mut self) {
-        self.model_xform = Mat4::IDENTITY;
+    pub fn set_camera(&mut self, camera: &Camera) {
+        for Renderable { material, .. } in &mut self.objs {
+            material.set_camera(camera);
+        }
     }
 }
@@ -0,0 +1,204 @@
+use std::{mem::size_of, path::PathBuf};
+use anyhow::Result;
+use bytemuck::NoUninit;
+use glam::{Mat4, Vec3, Vec4};
+use crate::{
+    camera::Camera,
+    gpu::Gpu,
+    light,
+    material::{self, Material},
+    texture::BuiltinTexture,
+    upload::FrameUploads,
+};
+
+/// Texture maps of a `PbrMaterial`. Missing maps leave the matching
+/// factor in `PbrFactors` to stand on its own, except for the emissive
+/// one which then emits nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PbrTextures {
+    /// sRGB, multiplied with `PbrFactors::base_color`.
+    pub base_color: Option<PathBuf>,
+    /// Metalness in the red channel.
+    pub metallic: Option<PathBuf>,
+    /// Perceptual roughness in the red channel.
+    pub roughness: Option<PathBuf>,
+    /// Tangent space normals.
+    pub normal: Option<PathBuf>,
+    /// Ambient occlusion in the red channel.
+    pub occlusion: Option<PathBuf>,
+    /// sRGB, multiplied with `PbrFactors::emissive`.
+    pub emissive: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PbrFactors {
+    /// Linear RGBA.
+    pub base_color: Vec4,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Linear RGB scale of the light given off by the emissive map.
+    pub emissive: Vec3,
+    /// How much of the occlusion map is applied, from 0 to 1.
+    pub occlusion_strength: f32,
+    /// Scales the X and Y of the sampled normals.
+    pub normal_scale: f32,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::ONE,
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: Vec3::ONE,
+            occlusion_strength: 1.0,
+            normal_scale: 1.0,
+        }
+    }
+}
+
+// Matches `Uniforms` in pbr.wgsl
+#[repr(C, packed)]
+#[derive(Copy, Clone, NoUninit)]
+struct UniformData {
+    projection: Mat4,
+    view: Mat4,
+    model: Mat4,
+    normal: Mat4,
+    camera_pos: Vec3,
+    _padding: f32,
+    base_color: Vec4,
+    emissive: Vec3,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    _padding2: f32,
+}
+
+// Fallback and sRGB flag of each map, in `PbrTextures` order
+const MAPS: [(BuiltinTexture, bool); 6] = [
+    (BuiltinTexture::White, true),       // base color
+    (BuiltinTexture::White, false),      // metallic
+    (BuiltinTexture::White, false),      // roughness
+    (BuiltinTexture::FlatNormal, false), // normal
+    (BuiltinTexture::White, false),      // occlusion
+    (BuiltinTexture::Black, true),       // emissive
+];
+
+/// Metallic-roughness material shaded with the Cook-Torrance BRDF, using
+/// the GGX distribution, Smith geometry term and Schlick's Fresnel.
+pub struct PbrMaterial {
+    pub factors: PbrFactors,
+    // Shared with every other material using the same pipeline key
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    projection: Mat4,
+    view: Mat4,
+    camera_pos: Vec3,
+    model: Mat4,
+    // Reloaded from the texture registry by `rebuild`
+    textures: PbrTextures,
+}
+
+impl PbrMaterial {
+    fn make_gpu_resources(gpu: &Gpu, textures: &PbrTextures)
+                          -> Result<(wgpu::BindGroup, wgpu::RenderPipeline)> {
+        let paths = [
+            &textures.base_color,
+            &textures.metallic,
+            &textures.roughness,
+            &textures.normal,
+            &textures.occlusion,
+            &textures.emissive,
+        ];
+
+        let sampler = wgpu::SamplerDescriptor {
+            label: "PBR texture sampler".into(),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        };
+        let (bind_group, layout) = material::textured_bind_group(
+            gpu,
+            "PBR material",
+            size_of::<UniformData>() as u64,
+            &paths.into_iter().zip(MAPS).collect::<Vec<_>>(),
+            &sampler
+        )?;
+
+        let shader = light::lit_shader(
+            "shaders/pbr.wgsl",
+            include_str!("shaders/pbr.wgsl"),
+            gpu.lights()
+        );
+        let key = material::lit_pipeline_key(gpu, shader, layout, false);
+        let pipeline = gpu.pipelines().render_pipeline(&gpu.device, gpu.pipeline_cache(), &key)?;
+
+        Ok((bind_group, pipeline))
+    }
+
+    pub fn new(gpu: &Gpu, textures: &PbrTextures, factors: PbrFactors) -> Result<Self> {
+        let (bind_group, pipeline) = Self::make_gpu_resources(gpu, textures)?;
+
+        Ok(Self {
+            factors,
+            pipeline,
+            bind_group,
+            projection: Mat4::IDENTITY,
+            view: Mat4::IDENTITY,
+            camera_pos: Vec3::ZERO,
+            model: Mat4::IDENTITY,
+            textures: textures.clone(),
+        })
+    }
+}
+
+impl Material for PbrMaterial {
+    fn set_render_pass(&self, render_pass: &mut wgpu::RenderPass,
+                       uploads: &mut FrameUploads) -> bool {
+        let factors = &self.factors;
+        let uniform_data = UniformData {
+            projection: self.projection,
+            view: self.view,
+            model: self.model,
+            normal: self.model.inverse().transpose(),
+            camera_pos: self.camera_pos,
+            _padding: 0.0,
+            base_color: factors.base_color,
+            emissive: factors.emissive,
+            metallic: factors.metallic.clamp(0.0, 1.0),
+            roughness: factors.roughness.clamp(0.0, 1.0),
+            occlusion_strength: factors.occlusion_strength,
+            normal_scale: factors.normal_scale,
+            _padding2: 0.0,
+        };
+
+        let Some(offset) = uploads.push_uniform(&uniform_data) else {
+            return false;
+        };
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[offset]);
+
+        true
+    }
+
+    fn set_camera(&mut self, camera: &Camera) {
+        self.projection = camera.projection_matrix();
+        self.view = camera.view_matrix();
+        self.camera_pos = camera.position;
+    }
+
+    fn set_model_xform(&mut self, transform: Mat4) {
+        self.model = transform;
+    }
+
+    fn rebuild(&mut self, gpu: &Gpu) -> Result<()> {
+        (self.bind_group, self.pipeline) = Self::make_gpu_resources(gpu, &self.textures)?;
+
+        Ok(())
+    }
+}
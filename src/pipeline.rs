@@ -0,0 +1,169 @@
+use std::{borrow::Cow, collections::HashMap, sync::{Mutex, MutexGuard}};
+use crate::error::{self, GpuError};
+
+/// WGSL source, identified by its text so that materials built from the
+/// same file share one shader module.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Shader {
+    pub label: &'static str,
+    pub source: Cow<'static, str>,
+}
+
+/// Owned counterpart of `wgpu::VertexBufferLayout`, usable as a map key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl VertexLayout {
+    fn as_wgpu(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.array_stride,
+            step_mode: self.step_mode,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+/// Everything a render pipeline is built from. Pipelines with equal keys
+/// are created once and shared.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub shader: Shader,
+    pub vertex_entry: &'static str,
+    /// `None` for depth-only pipelines.
+    pub fragment_entry: Option<&'static str>,
+    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pub vertex_layouts: Vec<VertexLayout>,
+    pub primitive: wgpu::PrimitiveState,
+    pub targets: Vec<Option<wgpu::ColorTargetState>>,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    pub sample_count: u32,
+}
+
+#[derive(Default)]
+struct Registry {
+    shaders: HashMap<Shader, wgpu::ShaderModule>,
+    bind_group_layouts: HashMap<Vec<wgpu::BindGroupLayoutEntry>, wgpu::BindGroupLayout>,
+    pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+}
+
+/// Shader modules, bind group layouts and render pipelines shared by every
+/// material on a device. Owned by `Gpu` and emptied when the device is
+/// replaced.
+#[derive(Default)]
+pub struct PipelineRegistry {
+    inner: Mutex<Registry>,
+}
+
+/// Locks the state of a registry shared between materials. Registries only
+/// ever insert finished entries, so a panic elsewhere can't have left one
+/// half-updated and a poisoned lock is safe to take over.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl PipelineRegistry {
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        lock(&self.inner)
+    }
+
+    /// Returns the layout for `entries`, creating it on first use. Failed
+    /// creations aren't cached, so the next call reports them again.
+    pub fn bind_group_layout(&self, device: &wgpu::Device, label: &str,
+                             entries: &[wgpu::BindGroupLayoutEntry])
+                             -> Result<wgpu::BindGroupLayout, GpuError> {
+        let mut registry = self.lock();
+
+        if let Some(layout) = registry.bind_group_layouts.get(entries) {
+            return Ok(layout.clone());
+        }
+
+        let layout = error::scoped(device, label, || {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries
+            })
+        })?;
+        registry.bind_group_layouts.insert(entries.to_vec(), layout.clone());
+
+        Ok(layout)
+    }
+
+    /// Returns the pipeline for `key`, creating it and its shader module on
+    /// first use. Like layouts, only successfully created ones are cached.
+    pub fn render_pipeline(&self, device: &wgpu::Device,
+                           cache: Option<&wgpu::PipelineCache>,
+                           key: &PipelineKey) -> Result<wgpu::RenderPipeline, GpuError> {
+        let mut registry = self.lock();
+
+        if let Some(pipeline) = registry.pipelines.get(key) {
+            return Ok(pipeline.clone());
+        }
+
+        let shader_module = match registry.shaders.get(&key.shader) {
+            Some(module) => module.clone(),
+            None => {
+                let module = error::scoped(device, key.shader.label, || {
+                    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some(key.shader.label),
+                        source: wgpu::ShaderSource::Wgsl(key.shader.source.clone())
+                    })
+                })?;
+                registry.shaders.insert(key.shader.clone(), module.clone());
+                module
+            }
+        };
+
+        let pipeline = error::scoped(device, key.shader.label, || {
+            let bind_group_layouts: Vec<_> = key.bind_group_layouts.iter().collect();
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(key.shader.label),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[]
+            });
+
+            let vertex_layouts: Vec<_> = key.vertex_layouts
+                .iter()
+                .map(VertexLayout::as_wgpu)
+                .collect();
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(key.shader.label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: Some(key.vertex_entry),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    buffers: &vertex_layouts
+                },
+                primitive: key.primitive,
+                fragment: key.fragment_entry.map(|entry_point| wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    targets: &key.targets
+                }),
+                depth_stencil: key.depth_stencil.clone(),
+                multisample: wgpu::MultisampleState {
+                    count: key.sample_count,
+                    mask: !0u64,
+                    alpha_to_coverage_enabled: false
+                },
+                multiview: None,
+                cache
+            })
+        })?;
+
+        log::debug!("Created render pipeline for {}", key.shader.label);
+        registry.pipelines.insert(key.clone(), pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    pub fn pipeline_count(&self) -> usize {
+        self.lock().pipelines.len()
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// A `wgpu::PipelineCache` backed by a file, so pipelines compiled in one
+/// run don't have to be compiled again in the next.
+pub struct PipelineCache {
+    cache: wgpu::PipelineCache,
+    path: PathBuf,
+}
+
+impl PipelineCache {
+    /// `$XDG_CACHE_HOME/webgpu`, `~/.cache/webgpu` or a temp directory.
+    pub fn default_dir() -> PathBuf {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir)
+            .join("webgpu")
+    }
+
+    // Caches are only valid for the exact device and driver that wrote them
+    fn file_name(adapter_info: &wgpu::AdapterInfo) -> Option<String> {
+        let key = wgpu::util::pipeline_cache_key(adapter_info)?;
+        let driver: String = format!("{}_{}", adapter_info.driver, adapter_info.driver_info)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+            .collect();
+
+        Some(format!("{key}_{driver}.bin"))
+    }
+
+    /// Returns `None` when the device or backend can't cache pipelines.
+    pub fn load(device: &wgpu::Device, adapter_info: &wgpu::AdapterInfo, dir: &Path)
+                -> Option<Self> {
+        if !device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return None;
+        }
+
+        let path = dir.join(Self::file_name(adapter_info)?);
+        let data = std::fs::read(&path).ok();
+
+        if data.is_some() {
+            log::info!("Loaded pipeline cache from {}", path.display());
+        }
+
+        // SAFETY: the data was produced by `get_data` for an adapter with
+        // the same vendor, device and driver. With `fallback` set, data
+        // the driver rejects just yields an empty cache.
+        let cache = unsafe {
+            device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: "Pipeline cache".into(),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+
+        Some(Self { cache, path })
+    }
+
+    pub fn get(&self) -> &wgpu::PipelineCache {
+        &self.cache
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(data) = self.cache.get_data() else {
+            return Ok(());
+        };
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        // Write to a temporary file first so a crash can't leave a
+        // truncated cache behind
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, &data)
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+const MAX_QUERIES: u32 = 64;
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+const AVERAGE_WINDOW: usize = 120;
+
+#[derive(Default)]
+struct RollingAverage {
+    samples: VecDeque<Duration>,
+}
+
+impl RollingAverage {
+    fn push(&mut self, sample: Duration) {
+        if self.samples.len() == AVERAGE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    fn average(&self) -> Duration {
+        self.samples.iter().sum::<Duration>() / self.samples.len().max(1) as u32
+    }
+}
+
+// A resolved copy of one frame's queries, waiting to be mapped
+struct InFlightFrame {
+    buffer: wgpu::Buffer,
+    scopes: Vec<(String, u32)>,
+    mapped: Arc<AtomicBool>,
+}
+
+struct TimestampQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    free_buffers: Vec<wgpu::Buffer>,
+    in_flight: Vec<InFlightFrame>,
+    // Pairs of (label, index of the start query); the end query follows it
+    scopes: Vec<(String, u32)>,
+    // Set when the last in-flight frame still has to start mapping
+    needs_map: bool,
+    timestamp_period: f32,
+    inside_passes: bool,
+}
+
+impl TimestampQueries {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: "Profiler queries".into(),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_QUERIES
+        });
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: "Profiler resolve buffer".into(),
+            size: MAX_QUERIES as u64 * wgpu::QUERY_SIZE as u64,
+            mapped_at_creation: false,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC
+        });
+
+        Self {
+            query_set,
+            resolve_buffer,
+            free_buffers: Vec::new(),
+            in_flight: Vec::new(),
+            scopes: Vec::new(),
+            needs_map: false,
+            timestamp_period: queue.get_timestamp_period(),
+            inside_passes: device.features().contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES),
+        }
+    }
+
+    fn allocate(&mut self, label: &str) -> Option<u32> {
+        let start = self.scopes.len() as u32 * 2;
+        if start + 2 > MAX_QUERIES {
+            return None;
+        }
+
+        self.scopes.push((label.to_owned(), start));
+        Some(start)
+    }
+}
+
+/// Optional frame profiler. Measures GPU time with timestamp queries when
+/// `Features::TIMESTAMP_QUERY` is enabled and falls back to CPU timings of
+/// the same scopes otherwise. Results arrive a few frames late and are
+/// reported as rolling averages per scope label.
+pub struct Profiler {
+    enabled: bool,
+    queries: Option<TimestampQueries>,
+    open_cpu_scopes: Vec<(String, Instant)>,
+    averages: BTreeMap<String, RollingAverage>,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, enabled: bool) -> Self {
+        let queries = (enabled && device.features().contains(wgpu::Features::TIMESTAMP_QUERY))
+            .then(|| TimestampQueries::new(device, queue));
+
+        if enabled && queries.is_none() {
+            log::info!("Timestamp queries unsupported, profiling with CPU timings");
+        }
+
+        Self {
+            enabled,
+            queries,
+            open_cpu_scopes: Vec::new(),
+            averages: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn uses_gpu_timestamps(&self) -> bool {
+        self.queries.is_some()
+    }
+
+    fn record(&mut self, label: String, duration: Duration) {
+        self.averages.entry(label).or_default().push(duration);
+    }
+
+    /// Collects the timings of frames the GPU has finished with.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        let Some(queries) = &mut self.queries else {
+            return;
+        };
+
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        let (finished, pending) = std::mem::take(&mut queries.in_flight)
+            .into_iter()
+            .partition(|frame| frame.mapped.load(Ordering::Acquire));
+        queries.in_flight = pending;
+
+        let period = queries.timestamp_period as f64;
+        let mut samples = Vec::new();
+
+        for frame in finished {
+            {
+                let data = frame.buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+
+                for (label, start) in frame.scopes {
+                    let ticks = timestamps[start as usize + 1]
+                        .saturating_sub(timestamps[start as usize]);
+                    let nanos = (ticks as f64 * period) as u64;
+                    samples.push((label, Duration::from_nanos(nanos)));
+                }
+            }
+
+            frame.buffer.unmap();
+            queries.free_buffers.push(frame.buffer);
+        }
+
+        for (label, duration) in samples {
+            self.record(label, duration);
+        }
+    }
+
+    /// Timestamp writes for a whole render pass. Without GPU timestamps
+    /// the pass is timed on the CPU until `end_pass` is called.
+    pub fn pass_timestamp_writes(&mut self, label: &str)
+                                 -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        if !self.enabled {
+            return None;
+        }
+
+        match &mut self.queries {
+            Some(queries) => {
+                let start = queries.allocate(label)?;
+
+                Some(wgpu::RenderPassTimestampWrites {
+                    query_set: &queries.query_set,
+                    beginning_of_pass_write_index: Some(start),
+                    end_of_pass_write_index: Some(start + 1),
+                })
+            }
+            None => {
+                self.open_cpu_scopes.push((label.to_owned(), Instant::now()));
+                None
+            }
+        }
+    }
+
+    pub fn end_pass(&mut self) {
+        if self.queries.is_none()
+            && let Some((label, start)) = self.open_cpu_scopes.pop() {
+            self.record(label, start.elapsed());
+        }
+    }
+
+    /// Times the draws recorded by `record` under `label`. Needs
+    /// `Features::TIMESTAMP_QUERY_INSIDE_PASSES` for GPU timings.
+    pub fn scope<R>(&mut self, label: &str, render_pass: &mut wgpu::RenderPass,
+                    record: impl FnOnce(&mut wgpu::RenderPass) -> R) -> R {
+        if !self.enabled {
+            return record(render_pass);
+        }
+
+        if let Some(queries) = &mut self.queries
+            && queries.inside_passes
+            && let Some(start) = queries.allocate(label) {
+            render_pass.write_timestamp(&queries.query_set, start);
+            let result = record(render_pass);
+            render_pass.write_timestamp(&queries.query_set, start + 1);
+
+            return result;
+        }
+
+        let start = Instant::now();
+        let result = record(render_pass);
+        self.record(label.to_owned(), start.elapsed());
+
+        result
+    }
+
+    /// Resolves this frame's queries into a buffer that's mapped once the
+    /// frame has been submitted.
+    pub fn end_frame(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let Some(queries) = &mut self.queries else {
+            return;
+        };
+
+        let scopes = std::mem::take(&mut queries.scopes);
+        if scopes.is_empty() {
+            return;
+        }
+
+        // Drop this frame's timings rather than stalling on old readbacks
+        if queries.in_flight.len() >= MAX_FRAMES_IN_FLIGHT {
+            return;
+        }
+
+        let query_count = scopes.len() as u32 * 2;
+        let size = MAX_QUERIES as u64 * wgpu::QUERY_SIZE as u64;
+        let buffer = queries.free_buffers.pop().unwrap_or_else(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: "Profiler readback buffer".into(),
+                size,
+                mapped_at_creation: false,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ
+            })
+        });
+
+        encoder.resolve_query_set(&queries.query_set, 0..query_count, &queries.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&queries.resolve_buffer, 0, &buffer, 0, size);
+
+        queries.in_flight.push(InFlightFrame {
+            buffer,
+            scopes,
+            mapped: Arc::new(AtomicBool::new(false)),
+        });
+        queries.needs_map = true;
+    }
+
+    /// Starts mapping the readback recorded by `end_frame`. Must be called
+    /// after the encoder was submitted.
+    pub fn after_submit(&mut self) {
+        let Some(queries) = &mut self.queries else {
+            return;
+        };
+
+        if std::mem::take(&mut queries.needs_map)
+            && let Some(frame) = queries.in_flight.last() {
+            let mapped = frame.mapped.clone();
+            frame.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+        }
+    }
+
+    pub fn averages(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.averages
+            .iter()
+            .map(|(label, average)| (label.as_str(), average.average()))
+    }
+
+    pub fn report(&self) -> String {
+        let source = if self.uses_gpu_timestamps() { "GPU" } else { "CPU" };
+
+        self.averages()
+            .map(|(label, average)| {
+                format!("{label}: {:.3} ms", average.as_secs_f64() * 1000.0)
+            })
+            .fold(format!("Frame timings ({source})"), |report, line| report + "\n  " + &line)
+    }
+}
@@ -0,0 +1,190 @@
+use std::borrow::Cow;
+use anyhow::{Result, anyhow, bail};
+use crate::{
+    error::GpuError,
+    gpu::Gpu,
+    pipeline::{PipelineKey, Shader},
+};
+
+/// A texture copy recorded into a command encoder, waiting for the
+/// encoder to be submitted before it can be mapped.
+pub struct PendingReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    swap_red_blue: bool,
+}
+
+impl PendingReadback {
+    /// Records a copy of `texture` into a mappable buffer. Only 8-bit
+    /// RGBA and BGRA formats (sRGB or not) can be read back.
+    pub fn copy_texture(device: &wgpu::Device,
+                        encoder: &mut wgpu::CommandEncoder,
+                        texture: &wgpu::Texture) -> Result<Self> {
+        let swap_red_blue = match texture.format().remove_srgb_suffix() {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            format => bail!("Can't read back pixels in {format:?} format"),
+        };
+
+        let (width, height) = (texture.width(), texture.height());
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: "Readback buffer".into(),
+            size: (padded_bytes_per_row * height) as u64,
+            mapped_at_creation: false,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height)
+                }
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1
+            }
+        );
+
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            swap_red_blue,
+        })
+    }
+
+    /// Blocks until the copy has finished and returns the pixels with the
+    /// row padding stripped. sRGB data is kept as is, which is what PNG
+    /// expects.
+    pub fn read(self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        rx.recv()??;
+
+        let unpadded_bytes_per_row = (self.width * 4) as usize;
+        let mut pixels: Vec<u8> = slice.get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row])
+            .copied()
+            .collect();
+        self.buffer.unmap();
+
+        if self.swap_red_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| anyhow!("Readback buffer has an unexpected size"))
+    }
+}
+
+/// Fullscreen draw copying a frame captured into an intermediate texture
+/// onto the surface, for surfaces that can't be copied from directly.
+pub(crate) struct BlitPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl BlitPass {
+    const SHADER: Shader = Shader {
+        label: "shaders/blit.wgsl",
+        source: Cow::Borrowed(include_str!("shaders/blit.wgsl")),
+    };
+
+    pub(crate) fn new(gpu: &Gpu, source: &wgpu::TextureView) -> Result<Self, GpuError> {
+        gpu.scoped("Capture blit", |device| {
+            let layout = gpu.pipelines().bind_group_layout(
+                device,
+                "Capture blit layout",
+                &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float {
+                            filterable: false
+                        },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                }]
+            )?;
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: "Capture blit".into(),
+                layout: &layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source)
+                }]
+            });
+
+            let key = PipelineKey {
+                shader: Self::SHADER,
+                vertex_entry: "vs_main",
+                fragment_entry: Some("fs_main"),
+                bind_group_layouts: vec![layout],
+                vertex_layouts: vec![],
+                primitive: wgpu::PrimitiveState::default(),
+                targets: vec![Some(wgpu::ColorTargetState {
+                    format: gpu.config.format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL
+                })],
+                depth_stencil: None,
+                sample_count: 1,
+            };
+
+            let pipeline = gpu.pipelines().render_pipeline(device, gpu.pipeline_cache(), &key)?;
+
+            Ok(Self { pipeline, bind_group })
+        })?
+    }
+
+    pub(crate) fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Capture Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                depth_slice: None,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -1,71 +1,239 @@
-use crate::{gpu::Gpu, material::SimpleMaterial, mesh::Mesh, object::Object};
-use winit::dpi::PhysicalSize;
-use anyhow::Result;
-use glam::{Vec2, Vec3};
-use std::path::Path;
+use crate::{
+    background::ClearPolicy,
+    camera::Camera,
+    controller::CameraController,
+    gpu::{Gpu, RenderStatus},
+    light::Light,
+    object::Object,
+    pbr::{PbrFactors, PbrMaterial, PbrTextures},
+    scene::{NodeId, Scene},
+    texture::TextureSearch,
+    transform::Transform,
+};
+use winit::{dpi::PhysicalSize, event::WindowEvent};
+use anyhow::{Result, anyhow, bail};
+use glam::{Quat, Vec3, Vec4};
+use std::{f32::consts::PI, path::Path, time::Duration};
+
+const PROFILER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct Renderer {
     begin: std::time::Instant,
+    last_report: std::time::Instant,
+    last_frame: std::time::Instant,
     gpu: Gpu,
-    objects: Vec<Object>
+    camera: Camera,
+    controller: Option<Box<dyn CameraController>>,
+    scene: Scene,
+    // Nodes spun around their Z axis every frame
+    spinners: Vec<NodeId>,
 }
 
 impl Renderer {
-    pub fn render(&mut self) -> Result<()> {
-        self.gpu.render(|render_pass| {
-            /*
-            for object in &mut self.objects {
-                let time = std::time::Instant::now()
-                    .duration_since(self.begin.clone())
-                    .as_secs_f32();
-
-                object.reset();
-                //object.translate(Vec3::new(0.0, 0.0, 2.0));
-                object.rotate_x(-2.0 * 3.14159 / 4.0);
-                object.rotate_z(time);
-                object.scale(Vec3::new(0.7, 0.7, 0.7));
-                //object.translate(Vec3::new(1.0, 0.0, 0.0));
-                
-                object.set_render_pass(render_pass, &self.gpu.queue);
-            }
-            */
+    fn recover_device(&mut self) -> Result<()> {
+        log::warn!("Recreating GPU resources after device loss");
+        pollster::block_on(self.gpu.recover())?;
+        self.scene.rebuild(&self.gpu)?;
+        self.save_pipeline_cache();
+
+        Ok(())
+    }
+
+    fn save_pipeline_cache(&self) {
+        if let Err(err) = self.gpu.save_pipeline_cache() {
+            log::warn!("Failed to save the pipeline cache: {err:#}");
+        }
+    }
+
+    pub fn render(&mut self) -> Result<RenderStatus> {
+        if self.gpu.is_device_lost() {
+            self.recover_device()?;
+        }
+
+        let dt = self.last_frame.elapsed().as_secs_f32();
+        self.last_frame = std::time::Instant::now();
+
+        if let Some(controller) = &mut self.controller {
+            controller.update(&mut self.camera, dt);
+        }
+
+        let time = std::time::Instant::now()
+            .duration_since(self.begin)
+            .as_secs_f32();
+
+        for &spinner in &self.spinners {
+            self.scene.set_rotation(spinner, Quat::from_rotation_z(time));
+        }
+
+        self.scene.set_camera(&self.camera);
+        self.gpu.set_lights(self.scene.ambient, self.scene.lights(), &self.camera);
 
-            let time = std::time::Instant::now()
-                .duration_since(self.begin.clone())
-                .as_secs_f32();
+        let status = self.gpu.render(|render_pass, context| {
+            if let Some(shadow) = &context.shadow {
+                self.scene.set_shadow_pass(render_pass, context.uploads, shadow);
+                return;
+            }
 
-            let obj0 = &mut self.objects[0];
+            context.profiler.scope("Scene", render_pass, |render_pass| {
+                self.scene.set_render_pass(render_pass, context.uploads);
+            });
+        })?;
 
-            obj0.reset();
-            obj0.translate(Vec3::new(-0.7, 0.0, 0.0));
-            obj0.rotate_x(-2.0 * 3.14159 / 4.0);
-            obj0.rotate_z(time);
-            obj0.scale(Vec3::new(0.6, 0.6, 0.6));
+        let profiler = self.gpu.profiler();
+        if profiler.is_enabled() && self.last_report.elapsed() >= PROFILER_REPORT_INTERVAL {
+            log::info!("{}", profiler.report());
+            self.last_report = std::time::Instant::now();
+        }
 
-            obj0.set_render_pass(render_pass, &self.gpu.queue);
+        Ok(status)
+    }
 
-            let obj1 = &mut self.objects[1];
+    // Each model hangs off a node placing it in the scene, with a child
+    // node underneath that spins it
+    fn add_spinning_object(scene: &mut Scene, object: Object, name: &str,
+                           translation: Vec3, tilt: f32, scale: f32) -> NodeId {
+        let object = scene.add_object(object);
 
-            obj1.reset();
-            obj1.scale(Vec3::new(0.8, 0.8, 0.8));
-            obj1.translate(Vec3::new(0.9, 0.0, 0.0));
-            obj1.rotate_x(-2.5 * 3.14159 / 4.0);
-            obj1.rotate_z(time);
+        let placement = scene.add_node(name, None);
+        scene.set_transform(placement, Transform {
+            translation,
+            rotation: Quat::from_rotation_x(tilt),
+            scale: Vec3::splat(scale),
+        });
 
-            obj1.set_render_pass(render_pass, &self.gpu.queue);
+        let spinner = scene.add_node(&format!("{name} spinner"), Some(placement));
+        scene.set_object(spinner, Some(object));
 
-        })
+        spinner
     }
 
-    pub fn new(gpu: Gpu) -> Self {
-        let obj1 = Object::load_obj(&gpu, &Path::new("src/res/models/sus/sus.obj")).unwrap();
-        let mut obj2 = Object::load_obj(&gpu, &Path::new("src/res/models/obamium/obamium.obj")).unwrap();
+    /// Loads the demo scene from the `models` directory under `assets`,
+    /// which is also searched for textures the models can't find.
+    pub fn new(gpu: Gpu, assets: &Path) -> Result<Self> {
+        let search = TextureSearch::new().with_root(assets);
+        let obj1 = Object::load_obj_with(&gpu, &assets.join("models/sus/sus.obj"), &search)?;
+        let obj2 = Object::load_obj_with(&gpu, &assets.join("models/obamium/obamium.obj"),
+                                         &search)?;
+
+        let mut teapot = Object::load_obj_with(&gpu, &assets.join("models/teapot/teapot.obj"),
+                                               &search)?;
+        let plastic = PbrFactors {
+            base_color: Vec4::new(1.0, 0.45, 0.1, 1.0),
+            metallic: 0.0,
+            roughness: 0.35,
+            ..Default::default()
+        };
+        for part in 0..teapot.part_count() {
+            let material = PbrMaterial::new(&gpu, &PbrTextures::default(), plastic)?;
+            teapot.set_material(part, Box::new(material));
+        }
+
+        let mut scene = Scene::new();
+        let spinners = vec![
+            Self::add_spinning_object(&mut scene, obj1, "sus",
+                                      Vec3::new(-0.7, 0.0, 0.0), -PI / 2.0, 0.6),
+            Self::add_spinning_object(&mut scene, obj2, "obamium",
+                                      Vec3::new(0.72, 0.0, 0.0), -2.5 * PI / 4.0, 0.8),
+            Self::add_spinning_object(&mut scene, teapot, "teapot",
+                                      Vec3::new(0.0, -0.6, -0.8), -PI / 2.0, 0.15),
+        ];
+
+        scene.add_light(Light::directional(Vec3::new(1.0, -0.5, 0.5), Vec3::ONE, 0.8)
+                        .with_shadows(true));
+        scene.add_light(Light::point(Vec3::new(1.5, 1.0, -1.5), 6.0,
+                                     Vec3::new(1.0, 0.8, 0.6), 2.0));
+
         let begin = std::time::Instant::now();
 
-        Self { begin, gpu, objects: vec![obj1, obj2] }
+        let camera = Camera::perspective(
+            Vec3::new(0.0, 0.0, -3.0),
+            Vec3::ZERO,
+            45.0_f32.to_radians(),
+            gpu.aspect_ratio()
+        );
+
+        let renderer = Self {
+            begin,
+            last_report: begin,
+            last_frame: begin,
+            gpu,
+            camera,
+            controller: None,
+            scene,
+            spinners,
+        };
+        renderer.save_pipeline_cache();
+
+        Ok(renderer)
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) -> Result<()> {
+        self.gpu.resize(size)?;
+
+        self.camera.set_aspect_ratio(self.gpu.aspect_ratio());
+
+        Ok(())
     }
 
-    pub fn resize(&mut self, size: PhysicalSize<u32>) {
-        self.gpu.resize(size);
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    pub fn set_controller(&mut self, controller: Option<Box<dyn CameraController>>) {
+        self.controller = controller;
+    }
+
+    /// Passes input to the camera controller, returns `true` if it was used.
+    pub fn window_event(&mut self, event: &WindowEvent) -> bool {
+        self.controller
+            .as_mut()
+            .is_some_and(|controller| controller.window_event(event))
+    }
+
+    pub fn set_clear_policy(&mut self, clear: ClearPolicy) -> Result<()> {
+        self.gpu.set_clear_policy(clear)?;
+        self.save_pipeline_cache();
+
+        Ok(())
+    }
+
+    pub fn read_pixels(&self) -> Result<image::RgbaImage> {
+        self.gpu.read_pixels()
+    }
+
+    /// Renders a frame and saves it to `path`, in any format the `image`
+    /// crate can infer from the extension.
+    pub fn screenshot(&mut self, path: &Path) -> Result<()> {
+        self.gpu.request_capture();
+
+        if self.render()? == RenderStatus::Skipped {
+            bail!("No frame was available to capture");
+        }
+
+        let image = self.gpu.take_capture()
+            .ok_or_else(|| anyhow!("Frame was rendered without being captured"))?;
+        image.save(path)?;
+
+        Ok(())
+    }
+}
+
+// Pipelines created for anything added to the scene after startup are
+// only written out here
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        self.save_pipeline_cache();
     }
 }
@@ -0,0 +1,323 @@
+use anyhow::{Result, bail};
+use glam::{Mat4, Quat, Vec3};
+use crate::{
+    camera::Camera, gpu::Gpu, light::Light, object::Object, shadow::ShadowPass,
+    transform::Transform, upload::FrameUploads
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectId(usize);
+
+pub struct Node {
+    pub name: String,
+    transform: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    object: Option<ObjectId>,
+    // Cached parent world matrix times the local transform
+    world: Mat4,
+    dirty: bool,
+}
+
+impl Node {
+    /// Transform relative to the parent node.
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn object(&self) -> Option<ObjectId> {
+        self.object
+    }
+
+    pub fn local_transform(&self) -> Mat4 {
+        self.transform.to_matrix()
+    }
+}
+
+/// Hierarchy of nodes with local `Transform`s. Nodes can draw a loaded
+/// `Object` at their world transform; several nodes may share one object.
+pub struct Scene {
+    nodes: Vec<Node>,
+    objects: Vec<Object>,
+    roots: Vec<NodeId>,
+    lights: Vec<Light>,
+    /// Linear RGB light reaching every surface regardless of the lights.
+    pub ambient: Vec3,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            objects: Vec::new(),
+            roots: Vec::new(),
+            lights: Vec::new(),
+            ambient: Vec3::splat(0.1),
+        }
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_light(&mut self, light: Light) -> usize {
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut Vec<Light> {
+        &mut self.lights
+    }
+
+    pub fn add_object(&mut self, object: Object) -> ObjectId {
+        self.objects.push(object);
+        ObjectId(self.objects.len() - 1)
+    }
+
+    pub fn object_mut(&mut self, id: ObjectId) -> &mut Object {
+        &mut self.objects[id.0]
+    }
+
+    /// Adds an identity-transformed node, at the root if `parent` is `None`.
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: name.to_owned(),
+            transform: Transform::IDENTITY,
+            parent,
+            children: Vec::new(),
+            object: None,
+            world: Mat4::IDENTITY,
+            dirty: true,
+        });
+
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn set_object(&mut self, id: NodeId, object: Option<ObjectId>) {
+        self.nodes[id.0].object = object;
+    }
+
+    /// Moves `id` under a new parent, keeping its local transform.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(node) = ancestor {
+            if node == id {
+                bail!("Node {:?} can't be parented to its own descendant",
+                      self.nodes[id.0].name);
+            }
+            ancestor = self.nodes[node.0].parent;
+        }
+
+        match self.nodes[id.0].parent {
+            Some(old) => self.nodes[old.0].children.retain(|&child| child != id),
+            None => self.roots.retain(|&root| root != id),
+        }
+
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        let node = &mut self.nodes[id.0];
+        node.parent = parent;
+        node.dirty = true;
+
+        Ok(())
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+        self.update_transform(id, |current| *current = transform);
+    }
+
+    /// Edits the local transform of `id` in place.
+    pub fn update_transform(&mut self, id: NodeId, update: impl FnOnce(&mut Transform)) {
+        let node = &mut self.nodes[id.0];
+        update(&mut node.transform);
+        node.dirty = true;
+    }
+
+    pub fn set_translation(&mut self, id: NodeId, translation: Vec3) {
+        self.update_transform(id, |transform| transform.translation = translation);
+    }
+
+    pub fn set_rotation(&mut self, id: NodeId, rotation: Quat) {
+        self.update_transform(id, |transform| transform.rotation = rotation);
+    }
+
+    pub fn set_scale(&mut self, id: NodeId, scale: Vec3) {
+        self.update_transform(id, |transform| transform.scale = scale);
+    }
+
+    /// World matrix as of the last `update_world_transforms` call.
+    pub fn world_transform(&self, id: NodeId) -> Mat4 {
+        self.nodes[id.0].world
+    }
+
+    /// Recomputes the world matrices of dirty nodes and their descendants.
+    pub fn update_world_transforms(&mut self) {
+        let mut stack: Vec<_> = self.roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY, false))
+            .collect();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            let changed = node.dirty || parent_changed;
+
+            if changed {
+                node.world = parent_world * node.local_transform();
+                node.dirty = false;
+            }
+
+            let world = node.world;
+            stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+        }
+    }
+
+    pub fn set_camera(&mut self, camera: &Camera) {
+        for object in &mut self.objects {
+            object.set_camera(camera);
+        }
+    }
+
+    pub fn set_render_pass(&mut self, render_pass: &mut wgpu::RenderPass, uploads: &mut FrameUploads) {
+        self.update_world_transforms();
+
+        for node in &self.nodes {
+            if let Some(object) = node.object {
+                self.objects[object.0].set_render_pass(render_pass, uploads, node.world);
+            }
+        }
+    }
+
+    /// Draws every object into the shadow map of `shadow`.
+    pub fn set_shadow_pass(&mut self, render_pass: &mut wgpu::RenderPass,
+                           uploads: &mut FrameUploads, shadow: &ShadowPass) {
+        self.update_world_transforms();
+
+        for node in &self.nodes {
+            if let Some(object) = node.object {
+                self.objects[object.0].set_shadow_pass(render_pass, uploads, node.world, shadow);
+            }
+        }
+    }
+
+    pub fn rebuild(&mut self, gpu: &Gpu) -> Result<()> {
+        for object in &mut self.objects {
+            object.rebuild(gpu)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(scene: &mut Scene) -> (NodeId, NodeId, NodeId) {
+        let root = scene.add_node("root", None);
+        let child = scene.add_node("child", Some(root));
+        let grandchild = scene.add_node("grandchild", Some(child));
+
+        scene.set_translation(root, Vec3::X);
+        scene.set_translation(child, Vec3::Y);
+        scene.set_translation(grandchild, Vec3::Z);
+
+        (root, child, grandchild)
+    }
+
+    fn world_position(scene: &Scene, id: NodeId) -> Vec3 {
+        scene.world_transform(id).transform_point3(Vec3::ZERO)
+    }
+
+    #[test]
+    fn world_transforms_follow_parents() {
+        let mut scene = Scene::new();
+        let (root, child, grandchild) = chain(&mut scene);
+
+        scene.update_world_transforms();
+        assert_eq!(world_position(&scene, grandchild), Vec3::ONE);
+        assert!(scene.nodes.iter().all(|node| !node.dirty));
+
+        scene.set_translation(root, Vec3::new(5.0, 0.0, 0.0));
+        assert!(!scene.node(child).dirty && !scene.node(grandchild).dirty);
+
+        scene.update_world_transforms();
+        assert_eq!(world_position(&scene, child), Vec3::new(5.0, 1.0, 0.0));
+        assert_eq!(world_position(&scene, grandchild), Vec3::new(5.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn clean_nodes_keep_their_world_transform() {
+        let mut scene = Scene::new();
+        let (root, child, _) = chain(&mut scene);
+        scene.update_world_transforms();
+
+        // Only dirty nodes get recomputed, so a stale cache survives
+        scene.nodes[root.0].world = Mat4::ZERO;
+        scene.set_translation(child, Vec3::NEG_Y);
+        scene.update_world_transforms();
+
+        assert_eq!(scene.world_transform(root), Mat4::ZERO);
+        assert_eq!(world_position(&scene, child), Vec3::ZERO);
+    }
+
+    #[test]
+    fn set_parent_moves_node() {
+        let mut scene = Scene::new();
+        let (root, child, grandchild) = chain(&mut scene);
+        scene.update_world_transforms();
+
+        scene.set_parent(grandchild, Some(root)).unwrap();
+        assert_eq!(scene.node(grandchild).parent(), Some(root));
+        assert_eq!(scene.node(root).children(), &[child, grandchild]);
+        assert!(scene.node(child).children().is_empty());
+
+        scene.update_world_transforms();
+        assert_eq!(world_position(&scene, grandchild), Vec3::new(1.0, 0.0, 1.0));
+
+        scene.set_parent(child, None).unwrap();
+        assert_eq!(scene.roots, vec![root, child]);
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut scene = Scene::new();
+        let (root, child, grandchild) = chain(&mut scene);
+
+        assert!(scene.set_parent(root, Some(grandchild)).is_err());
+        assert!(scene.set_parent(child, Some(child)).is_err());
+
+        assert_eq!(scene.node(root).parent(), None);
+        assert_eq!(scene.node(child).parent(), Some(root));
+        assert_eq!(scene.roots, vec![root]);
+    }
+}
@@ -0,0 +1,28 @@
+@group(0) @binding(0) var<uniform> gradient: Gradient;
+
+struct Gradient {
+    top: vec4f,
+    bottom: vec4f,
+}
+
+struct VertexOutput {
+    @builtin(position) pos: vec4f,
+    @location(0) height: f32,
+};
+
+// A single triangle covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.pos = vec4f(uv * 2.0 - 1.0, 1.0, 1.0);
+    out.height = uv.y;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    // Colors come already encoded for the target format
+    return mix(gradient.bottom, gradient.top, clamp(in.height, 0.0, 1.0));
+}
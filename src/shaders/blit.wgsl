@@ -0,0 +1,14 @@
+@group(0) @binding(0) var source: texture_2d<f32>;
+
+// A single triangle covering the whole screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4f {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4f(uv * 2.0 - 1.0, 1.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    // Same size and format as the target, so texels map one to one
+    return textureLoad(source, vec2i(pos.xy), 0);
+}
@@ -0,0 +1,116 @@
+// Shared by every lit material, MAX_LIGHTS and MAX_SHADOW_MAPS are
+// prepended by the renderer
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3f,
+    kind: u32,
+    direction: vec3f,
+    range: f32,
+    color: vec3f,
+    intensity: f32,
+    cos_inner: f32,
+    cos_outer: f32,
+    // First of the light's shadow maps, negative without shadows
+    shadow_map: i32,
+}
+
+struct Lights {
+    ambient: vec3f,
+    count: u32,
+    camera_position: vec3f,
+    cascade_count: u32,
+    camera_forward: vec3f,
+    normal_bias: f32,
+    // View depth at which each directional light cascade ends
+    cascade_splits: vec4f,
+    shadow_matrices: array<mat4x4f, MAX_SHADOW_MAPS>,
+    lights: array<Light, MAX_LIGHTS>,
+}
+
+@group(1) @binding(0) var<uniform> uLights: Lights;
+@group(1) @binding(1) var shadow_maps: texture_depth_2d_array;
+@group(1) @binding(2) var shadow_sampler: sampler_comparison;
+
+struct LightSample {
+    // Unit vector from the surface towards the light
+    direction: vec3f,
+    radiance: vec3f,
+}
+
+// Smoothly fades point and spot lights out towards their range
+fn range_falloff(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / max(distance * distance, 0.0001);
+}
+
+fn sample_light(light: Light, world_pos: vec3f) -> LightSample {
+    var out: LightSample;
+
+    if light.kind == LIGHT_DIRECTIONAL {
+        out.direction = -light.direction;
+        out.radiance = light.color * light.intensity;
+        return out;
+    }
+
+    let to_light = light.position - world_pos;
+    let distance = length(to_light);
+    out.direction = to_light / max(distance, 0.0001);
+
+    var attenuation = range_falloff(distance, light.range);
+    if light.kind == LIGHT_SPOT {
+        let cos_angle = dot(-out.direction, light.direction);
+        attenuation *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+    }
+
+    out.radiance = light.color * light.intensity * attenuation;
+    return out;
+}
+
+// Fraction of the light reaching `world_pos`, averaged over a 3x3 texel
+// neighbourhood to soften the shadow edges
+fn shadow_factor(light: Light, world_pos: vec3f, normal: vec3f) -> f32 {
+    if light.shadow_map < 0 {
+        return 1.0;
+    }
+
+    var map = light.shadow_map;
+    if light.kind == LIGHT_DIRECTIONAL {
+        let depth = dot(world_pos - uLights.camera_position, uLights.camera_forward);
+        if depth > uLights.cascade_splits[uLights.cascade_count - 1u] {
+            return 1.0;
+        }
+
+        var cascade = 0u;
+        for (var i = 0u; i + 1u < uLights.cascade_count; i++) {
+            if depth > uLights.cascade_splits[i] {
+                cascade = i + 1u;
+            }
+        }
+        map += i32(cascade);
+    }
+
+    let offset_pos = world_pos + normal * uLights.normal_bias;
+    let clip = uLights.shadow_matrices[map] * vec4f(offset_pos, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2f(0.5, -0.5) + 0.5;
+
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2f(textureDimensions(shadow_maps));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2f(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_maps, shadow_sampler, uv + offset, map, ndc.z);
+        }
+    }
+
+    return lit / 9.0;
+}
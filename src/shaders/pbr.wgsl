@@ -0,0 +1,141 @@
+@group(0) @binding(0) var<uniform> uInput: Uniforms;
+@group(0) @binding(1) var base_color_map: texture_2d<f32>;
+@group(0) @binding(2) var metallic_map: texture_2d<f32>;
+@group(0) @binding(3) var roughness_map: texture_2d<f32>;
+@group(0) @binding(4) var normal_map: texture_2d<f32>;
+@group(0) @binding(5) var occlusion_map: texture_2d<f32>;
+@group(0) @binding(6) var emissive_map: texture_2d<f32>;
+@group(0) @binding(7) var sampl: sampler;
+
+const PI: f32 = 3.14159265;
+
+struct Uniforms {
+    projection: mat4x4f,
+    view: mat4x4f,
+    model: mat4x4f,
+    normal: mat4x4f,
+    camera_pos: vec3f,
+    base_color: vec4f,
+    emissive: vec3f,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+}
+
+struct VertexInput {
+    @location(0) pos: vec3f,
+    @location(1) tangent: vec3f,
+    @location(2) bitangent: vec3f,
+    @location(3) normal: vec3f,
+    @location(4) uv: vec2f,
+};
+
+struct VertexOutput {
+    @builtin(position) pos: vec4f,
+    @location(0) tangent: vec3f,
+    @location(1) bitangent: vec3f,
+    @location(2) normal: vec3f,
+    @location(3) world_pos: vec3f,
+    @location(4) uv: vec2f,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world_pos = uInput.model * vec4f(in.pos, 1.0);
+    let out_pos = uInput.projection * uInput.view * world_pos;
+
+    let normal = (uInput.normal * vec4f(in.normal, 0.0)).xyz;
+    let tangent = (uInput.normal * vec4f(in.tangent, 0.0)).xyz;
+    let bitangent = (uInput.normal * vec4f(in.bitangent, 0.0)).xyz;
+
+    return VertexOutput(out_pos, tangent, bitangent, normal, world_pos.xyz, in.uv);
+}
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// Smith's masking-shadowing with the Schlick-GGX approximation
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view * light;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3f) -> vec3f {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Karis' fit of the split-sum environment BRDF, lets metals pick up the
+// ambient light without an environment map
+fn ambient_specular(f0: vec3f, roughness: f32, n_dot_v: f32) -> vec3f {
+    let c0 = vec4f(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = vec4f(1.0, 0.0425, 1.04, -0.04);
+    let r = roughness * c0 + c1;
+    let a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    let ab = vec2f(-1.04, 1.04) * a004 + r.zw;
+    return f0 * ab.x + ab.y;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let base_color = uInput.base_color * textureSample(base_color_map, sampl, in.uv);
+    let metallic = uInput.metallic * textureSample(metallic_map, sampl, in.uv).r;
+    // Perfectly smooth surfaces turn point lights into invisible specks
+    let roughness = clamp(uInput.roughness * textureSample(roughness_map, sampl, in.uv).r,
+                          0.045, 1.0);
+    let occlusion = mix(1.0, textureSample(occlusion_map, sampl, in.uv).r,
+                        uInput.occlusion_strength);
+    let emissive = uInput.emissive * textureSample(emissive_map, sampl, in.uv).rgb;
+
+    var local_normal = textureSample(normal_map, sampl, in.uv).xyz * 2.0 - 1.0;
+    local_normal = vec3f(local_normal.xy * uInput.normal_scale, local_normal.z);
+    let geometric_normal = normalize(in.normal);
+    let local_to_world = mat3x3f(
+        normalize(in.tangent),
+        normalize(in.bitangent),
+        geometric_normal
+    );
+    let normal = normalize(local_to_world * local_normal);
+
+    let view_direction = normalize(uInput.camera_pos - in.world_pos);
+    let n_dot_v = max(dot(normal, view_direction), 0.0001);
+    let alpha = roughness * roughness;
+
+    // Dielectrics reflect about 4% head-on, metals tint it with their color
+    let f0 = mix(vec3f(0.04), base_color.rgb, metallic);
+    let diffuse_color = base_color.rgb * (1.0 - metallic);
+
+    let ambient = diffuse_color + ambient_specular(f0, roughness, n_dot_v);
+    var color = uLights.ambient * ambient * occlusion + emissive;
+
+    for (var i = 0u; i < min(uLights.count, MAX_LIGHTS); i++) {
+        let light_data = uLights.lights[i];
+        let light = sample_light(light_data, in.world_pos);
+
+        let n_dot_l = dot(normal, light.direction);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+
+        let half_dir = normalize(view_direction + light.direction);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+        let v_dot_h = max(dot(view_direction, half_dir), 0.0);
+
+        let fresnel = fresnel_schlick(v_dot_h, f0);
+        let specular = fresnel * distribution_ggx(n_dot_h, alpha)
+            * geometry_smith(n_dot_v, n_dot_l, roughness)
+            / (4.0 * n_dot_v * n_dot_l);
+        let diffuse = (1.0 - fresnel) * diffuse_color / PI;
+
+        let shadow = shadow_factor(light_data, in.world_pos, geometric_normal);
+        color += (diffuse + specular) * light.radiance * n_dot_l * shadow;
+    }
+
+    return vec4f(color, 1.0);
+}
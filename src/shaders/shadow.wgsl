@@ -0,0 +1,8 @@
+// Depth-only pass drawing shadow casters from a light's point of view
+
+@group(0) @binding(0) var<uniform> uLightModel: mat4x4f;
+
+@vertex
+fn vs_main(@location(0) pos: vec3f) -> @builtin(position) vec4f {
+    return uLightModel * vec4f(pos, 1.0);
+}
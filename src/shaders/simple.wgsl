@@ -1,7 +1,10 @@
 @group(0) @binding(0) var<uniform> uInput: BindingInput;
 @group(0) @binding(1) var text: texture_2d<f32>;
 @group(0) @binding(2) var norm: texture_2d<f32>;
-@group(0) @binding(3) var sampl: sampler;
+@group(0) @binding(3) var specular_map: texture_2d<f32>;
+@group(0) @binding(4) var shininess_map: texture_2d<f32>;
+@group(0) @binding(5) var dissolve_map: texture_2d<f32>;
+@group(0) @binding(6) var sampl: sampler;
 
 struct BindingInput {
     projection: mat4x4f,
@@ -9,7 +12,12 @@ struct BindingInput {
     model: mat4x4f,
     normal: mat4x4f,
     camera_pos: vec3f,
-    time: f32
+    time: f32,
+    ambient: vec3f,
+    shininess: f32,
+    diffuse: vec3f,
+    dissolve: f32,
+    specular: vec3f,
 }
 
 struct VertexInput {
@@ -27,6 +35,7 @@ struct VertexOutput {
     @location(2) normal: vec3f,
     @location(3) view_direction: vec3f,
     @location(4) uv: vec2f,
+    @location(5) world_pos: vec3f,
 };
 
 @vertex
@@ -39,12 +48,11 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     let tangent = (uInput.normal * vec4f(in.tangent, 0.0)).xyz;
     let bitangent = (uInput.normal * vec4f(in.bitangent, 0.0)).xyz;
 
-    return VertexOutput(out_pos, tangent, bitangent, normal, view_direction, in.uv);
+    return VertexOutput(out_pos, tangent, bitangent, normal, view_direction, in.uv, world_pos.xyz);
 }
 
 @fragment
 fn fs_main(in: VertexOutput, @builtin(front_facing) face: bool) -> @location(0) vec4f {
-    let light = vec3f(10.0, 0.0, 0.0); 
     let texture_sample = textureSample(text, sampl, in.uv);
     let normal_sample = textureSample(norm, sampl, in.uv);
     let local_normal = normal_sample.rgb * 2.0 - 1.0;
@@ -55,15 +63,31 @@ fn fs_main(in: VertexOutput, @builtin(front_facing) face: bool) -> @location(0)
     );
     let world_normal = local_to_world * local_normal;
     let strength = 0.5;
-    let normal = mix(in.normal, world_normal, strength);
-    
-    let diffuse = max(0.3, dot(-light, normal)) * texture_sample;
-    //let diffuse = vec4f(world_normal, 1.0);
-    
-    let half_dir = normalize(normalize(in.view_direction) + normalize(light));
-    let angle = max(0.0, dot(normal, half_dir));
-    let hardness = 32.0;
-    let specular = 0.4*vec4f(vec3f(pow(angle, hardness)), 1.0);
-    
-    return diffuse + specular;
+    let normal = normalize(mix(in.normal, world_normal, strength));
+    let view_direction = normalize(in.view_direction);
+
+    let albedo = uInput.diffuse * texture_sample.rgb;
+    let specular_color = uInput.specular * textureSample(specular_map, sampl, in.uv).rgb;
+    // An exponent below 1 would light up the whole hemisphere
+    let shininess = max(uInput.shininess * textureSample(shininess_map, sampl, in.uv).r, 1.0);
+    let alpha = uInput.dissolve * textureSample(dissolve_map, sampl, in.uv).r;
+
+    var color = uLights.ambient * uInput.ambient * texture_sample.rgb;
+
+    for (var i = 0u; i < min(uLights.count, MAX_LIGHTS); i++) {
+        let light_data = uLights.lights[i];
+        let light = sample_light(light_data, in.world_pos);
+        let shadow = shadow_factor(light_data, in.world_pos, normalize(in.normal));
+
+        let diffuse = max(0.0, dot(light.direction, normal)) * albedo;
+        //let diffuse = world_normal;
+
+        let half_dir = normalize(view_direction + light.direction);
+        let angle = max(0.0, dot(normal, half_dir));
+        let specular = specular_color * pow(angle, shininess);
+
+        color += (diffuse + specular) * light.radiance * shadow;
+    }
+
+    return vec4f(color, alpha);
 }
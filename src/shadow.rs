@@ -0,0 +1,319 @@
+use std::{borrow::Cow, f32::consts::PI, mem::size_of};
+use glam::{Mat4, Vec3};
+use crate::{
+    camera::Camera,
+    data::Vertex,
+    error::GpuError,
+    light::{Light, LightKind},
+    mesh::Mesh,
+    pipeline::{PipelineKey, PipelineRegistry, Shader, VertexLayout},
+    upload::FrameUploads,
+};
+
+/// Most cascades a directional light can be split into.
+pub const MAX_CASCADES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of each shadow map in texels.
+    pub map_size: u32,
+    /// Shadow maps shared by all shadow casting lights. Directional lights
+    /// take one per cascade, spot lights a single one.
+    pub max_maps: u32,
+    /// Cascades per directional light, up to `MAX_CASCADES`.
+    pub cascades: u32,
+    /// How far from the camera directional light shadows reach.
+    pub distance: f32,
+    /// Blends the cascade splits from even (0) to logarithmic (1).
+    pub split_lambda: f32,
+    /// Constant and slope scaled depth bias applied when rendering the
+    /// shadow maps.
+    pub depth_bias: i32,
+    pub slope_bias: f32,
+    /// World units the lookup is pushed along the surface normal, against
+    /// acne on surfaces facing away from the light.
+    pub normal_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            map_size: 2048,
+            max_maps: 4,
+            cascades: 3,
+            distance: 20.0,
+            split_lambda: 0.5,
+            depth_bias: 2,
+            slope_bias: 2.0,
+            normal_bias: 0.02,
+        }
+    }
+}
+
+impl ShadowSettings {
+    pub(crate) fn cascade_count(&self) -> u32 {
+        self.cascades.clamp(1, MAX_CASCADES)
+    }
+
+    /// View depth at which each cascade ends.
+    pub(crate) fn cascade_splits(&self, camera: &Camera) -> [f32; MAX_CASCADES as usize] {
+        let (near, far) = camera.depth_range();
+        let far = far.min(self.distance).max(near);
+        let count = self.cascade_count();
+
+        let mut splits = [far; MAX_CASCADES as usize];
+        for (i, split) in splits.iter_mut().enumerate().take(count as usize) {
+            let t = (i + 1) as f32 / count as f32;
+            let even = near + (far - near) * t;
+            let log = near * (far / near).powf(t);
+            *split = even + (log - even) * self.split_lambda;
+        }
+
+        splits
+    }
+
+    /// Light view-projection matrices of the maps `light` renders into,
+    /// empty for lights that can't cast shadows.
+    pub(crate) fn light_matrices(&self, light: &Light, camera: &Camera,
+                                 splits: &[f32; MAX_CASCADES as usize]) -> Vec<Mat4> {
+        match light.kind {
+            LightKind::Directional { direction } => {
+                let direction = direction.normalize_or(Vec3::NEG_Y);
+                let (near, _) = camera.depth_range();
+
+                let mut start = near;
+                splits[..self.cascade_count() as usize]
+                    .iter()
+                    .map(|&end| {
+                        let matrix = self.cascade_matrix(camera, direction, start, end);
+                        start = end;
+                        matrix
+                    })
+                    .collect()
+            }
+            LightKind::Spot { position, direction, range, outer_angle, .. } => {
+                let direction = direction.normalize_or(Vec3::NEG_Y);
+                let view = Mat4::look_to_lh(position, direction, up_for(direction));
+                let fov = (outer_angle * 2.0).clamp(0.01, PI - 0.01);
+                let projection = Mat4::perspective_lh(fov, 1.0, range * 0.01, range);
+
+                vec![projection * view]
+            }
+            LightKind::Point { .. } => Vec::new(),
+        }
+    }
+
+    // Fits an orthographic projection around the bounding sphere of the
+    // camera frustum slice, which keeps its size constant while the camera
+    // turns. Snapping it to whole texels stops the edges from shimmering.
+    fn cascade_matrix(&self, camera: &Camera, direction: Vec3, near: f32, far: f32) -> Mat4 {
+        let corners = camera.frustum_corners(near, far);
+        let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(center))
+            .fold(0.0, f32::max)
+            .max(0.001);
+
+        let up = up_for(direction);
+        let rotation = Mat4::look_to_lh(Vec3::ZERO, direction, up);
+        let texel = radius * 2.0 / self.map_size as f32;
+        let mut snapped = rotation.transform_point3(center);
+        snapped.x = (snapped.x / texel).floor() * texel;
+        snapped.y = (snapped.y / texel).floor() * texel;
+        let center = rotation.inverse().transform_point3(snapped);
+
+        // Casters up to `distance` behind the slice still throw shadows into it
+        let reach = radius + self.distance;
+        let view = Mat4::look_to_lh(center - direction * reach, direction, up);
+        let projection = Mat4::orthographic_lh(-radius, radius, -radius, radius,
+                                               0.0, reach + radius);
+
+        projection * view
+    }
+}
+
+fn up_for(direction: Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y }
+}
+
+/// Depth array every shadow casting light renders into, sampled by lit
+/// materials through a comparison sampler.
+pub(crate) struct ShadowMaps {
+    layers: Vec<wgpu::TextureView>,
+    array_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+}
+
+impl ShadowMaps {
+    pub(crate) const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    pub(crate) fn new(device: &wgpu::Device, settings: &ShadowSettings) -> Self {
+        // GL treats single layer textures as plain 2D ones, which can't be
+        // viewed as an array
+        let layer_count = settings.max_maps.max(2);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: "Shadow maps".into(),
+            size: wgpu::Extent3d {
+                width: settings.map_size,
+                height: settings.map_size,
+                depth_or_array_layers: layer_count
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[]
+        });
+
+        let layers = (0..settings.max_maps)
+            .map(|layer| texture.create_view(&wgpu::TextureViewDescriptor {
+                label: "Shadow map layer".into(),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            }))
+            .collect();
+
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: "Shadow map array".into(),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: "Shadow sampler".into(),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        Self { layers, array_view, sampler }
+    }
+
+    pub(crate) fn layer(&self, index: usize) -> &wgpu::TextureView {
+        &self.layers[index]
+    }
+
+    pub(crate) fn array_view(&self) -> &wgpu::TextureView {
+        &self.array_view
+    }
+
+    pub(crate) fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+}
+
+/// Depth-only pipeline drawing shadow casters into a shadow map.
+pub(crate) struct ShadowCaster {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowCaster {
+    const SHADER: Shader = Shader {
+        label: "shaders/shadow.wgsl",
+        source: Cow::Borrowed(include_str!("shaders/shadow.wgsl")),
+    };
+
+    pub(crate) fn new(device: &wgpu::Device, pipelines: &PipelineRegistry,
+                      cache: Option<&wgpu::PipelineCache>, uploads: &FrameUploads,
+                      settings: &ShadowSettings) -> Result<Self, GpuError> {
+        let size = size_of::<Mat4>() as u64;
+
+        let layout = pipelines.bind_group_layout(
+            device,
+            "Shadow caster layout",
+            &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(size)
+                },
+                count: None
+            }]
+        )?;
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: "Shadow caster".into(),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uploads.uniform_binding(size)
+            }]
+        });
+
+        let key = PipelineKey {
+            shader: Self::SHADER,
+            vertex_entry: "vs_main",
+            fragment_entry: None,
+            bind_group_layouts: vec![layout],
+            vertex_layouts: vec![VertexLayout {
+                array_stride: size_of::<Vertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: vec![wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x3,
+                    offset: 0,
+                    shader_location: 0
+                }],
+            }],
+            primitive: wgpu::PrimitiveState::default(),
+            targets: vec![],
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: ShadowMaps::FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: settings.depth_bias,
+                    slope_scale: settings.slope_bias,
+                    clamp: 0.0
+                }
+            }),
+            sample_count: 1,
+        };
+
+        let pipeline = pipelines.render_pipeline(device, cache, &key)?;
+
+        Ok(Self { pipeline, bind_group })
+    }
+}
+
+/// Handed to the render callback while a shadow map is being drawn, in
+/// place of the main pass.
+pub struct ShadowPass<'a> {
+    caster: &'a ShadowCaster,
+    view_projection: Mat4,
+}
+
+impl<'a> ShadowPass<'a> {
+    pub(crate) fn new(caster: &'a ShadowCaster, view_projection: Mat4) -> Self {
+        Self { caster, view_projection }
+    }
+
+    /// The light's view-projection matrix for this shadow map.
+    pub fn view_projection(&self) -> Mat4 {
+        self.view_projection
+    }
+
+    /// Draws `mesh` as a shadow caster. Returns `false` if it was skipped
+    /// because the uniform ring is full.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, uploads: &mut FrameUploads,
+                model: Mat4, mesh: &Mesh) -> bool {
+        let Some(offset) = uploads.push_uniform(&(self.view_projection * model)) else {
+            return false;
+        };
+
+        render_pass.set_pipeline(&self.caster.pipeline);
+        render_pass.set_bind_group(0, &self.caster.bind_group, &[offset]);
+        mesh.set_render_pass(render_pass);
+
+        true
+    }
+}
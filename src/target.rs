@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use winit::window::Window;
+use crate::error::{self, GpuError};
+
+/// Color attachment that `Gpu::render` draws into - either a window
+/// swapchain or an offscreen texture that can be read back.
+pub enum RenderTarget {
+    Surface {
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+    },
+    Offscreen {
+        texture: wgpu::Texture,
+    },
+}
+
+pub struct Frame {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    surface_texture: Option<wgpu::SurfaceTexture>,
+}
+
+impl Frame {
+    pub fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
+}
+
+impl RenderTarget {
+    pub(crate) fn make_offscreen_texture(device: &wgpu::Device,
+                              config: &wgpu::SurfaceConfiguration)
+    -> wgpu::Texture {
+        let texture_desc = wgpu::TextureDescriptor {
+            label: "Offscreen color texture".into(),
+            dimension: wgpu::TextureDimension::D2,
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &[]
+        };
+
+        device.create_texture(&texture_desc)
+    }
+
+    pub fn offscreen(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration)
+                     -> Result<Self, GpuError> {
+        let texture = error::scoped(device, "Offscreen color texture", || {
+            Self::make_offscreen_texture(device, config)
+        })?;
+
+        Ok(Self::Offscreen { texture })
+    }
+
+    pub fn configure(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration)
+                     -> Result<(), GpuError> {
+        error::scoped(device, "Render target", || match self {
+            Self::Surface { surface, .. } => surface.configure(device, config),
+            Self::Offscreen { texture } => {
+                *texture = Self::make_offscreen_texture(device, config);
+            }
+        })
+    }
+
+    /// Reapplies the current configuration to a lost or outdated surface.
+    pub fn reconfigure(&self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        if let Self::Surface { surface, .. } = self {
+            surface.configure(device, config);
+        }
+    }
+
+    pub fn acquire(&self) -> Result<Frame, wgpu::SurfaceError> {
+        let frame = match self {
+            Self::Surface { surface, .. } => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+
+                Frame {
+                    texture: output.texture.clone(),
+                    view,
+                    surface_texture: Some(output),
+                }
+            }
+            Self::Offscreen { texture } => Frame {
+                texture: texture.clone(),
+                view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                surface_texture: None,
+            },
+        };
+
+        Ok(frame)
+    }
+
+    pub fn offscreen_texture(&self) -> Option<&wgpu::Texture> {
+        match self {
+            Self::Surface { .. } => None,
+            Self::Offscreen { texture } => Some(texture),
+        }
+    }
+
+    pub fn request_redraw(&self) {
+        if let Self::Surface { window, .. } = self {
+            window.request_redraw();
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use anyhow::{Context, Result, bail};
+use wgpu::{Extent3d, TexelCopyBufferLayout};
+use crate::pipeline;
+
+/// Where texture names from MTL files are looked up. Names are tried
+/// relative to the MTL's own directory first and then under each root in
+/// order. Files exported with absolute paths from another machine are
+/// also found by their bare file name in the same places.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextureSearch {
+    pub roots: Vec<PathBuf>,
+}
+
+impl TextureSearch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.roots.push(root.into());
+        self
+    }
+
+    /// Finds the file `name` refers to in an MTL file inside `mtl_dir`.
+    pub fn resolve(&self, mtl_dir: &Path, name: &str) -> Result<PathBuf> {
+        let candidates = self.candidates(mtl_dir, Path::new(name));
+
+        if let Some(found) = candidates.iter().find(|path| path.is_file()) {
+            return Ok(found.clone());
+        }
+
+        let tried: Vec<_> = candidates.iter().map(|path| path.display().to_string()).collect();
+        bail!("Texture {name:?} not found, tried:\n  {}", tried.join("\n  "))
+    }
+
+    fn candidates(&self, mtl_dir: &Path, name: &Path) -> Vec<PathBuf> {
+        let dirs = std::iter::once(mtl_dir).chain(self.roots.iter().map(PathBuf::as_path));
+
+        // Joining an absolute name keeps it as is
+        let mut candidates: Vec<_> = dirs.clone().map(|dir| dir.join(name)).collect();
+
+        if let Some(file_name) = name.file_name()
+            && Path::new(file_name) != name {
+            candidates.extend(dirs.map(|dir| dir.join(file_name)));
+        }
+
+        candidates.dedup();
+        candidates
+    }
+}
+
+/// Textures generated in memory, used where a material has no map or its
+/// file couldn't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinTexture {
+    White,
+    Black,
+    /// Tangent space normal pointing straight out of the surface.
+    FlatNormal,
+    /// Magenta and black checker standing in for files that failed to load.
+    Missing,
+}
+
+impl BuiltinTexture {
+    const CHECKER_SIZE: u32 = 64;
+    const CHECKER_CELL: u32 = 8;
+
+    fn image(self) -> image::RgbaImage {
+        let pixel = |rgba| image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba));
+
+        match self {
+            Self::White => pixel([255, 255, 255, 255]),
+            Self::Black => pixel([0, 0, 0, 255]),
+            Self::FlatNormal => pixel([128, 128, 255, 255]),
+            Self::Missing => {
+                image::RgbaImage::from_fn(Self::CHECKER_SIZE, Self::CHECKER_SIZE, |x, y| {
+                    if (x / Self::CHECKER_CELL + y / Self::CHECKER_CELL) % 2 == 0 {
+                        image::Rgba([255, 0, 255, 255])
+                    } else {
+                        image::Rgba([0, 0, 0, 255])
+                    }
+                })
+            }
+        }
+    }
+}
+
+fn load_image(path: &Path) -> Result<image::RgbaImage> {
+    let texture_bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read texture {}", path.display()))?;
+    let image = image::load_from_memory(&texture_bytes)
+        .with_context(|| format!("Failed to decode texture {}", path.display()))?;
+
+    Ok(image.to_rgba8())
+}
+
+fn make_texture(device: &wgpu::Device, queue: &wgpu::Queue,
+                texture_rgba: &image::RgbaImage, format: wgpu::TextureFormat)
+                -> wgpu::TextureView {
+    let (tex_width, tex_height) = texture_rgba.dimensions();
+    let extent = Extent3d {
+        width: tex_width,
+        height: tex_height,
+        depth_or_array_layers: 1
+    };
+
+    let descriptor = wgpu::TextureDescriptor {
+        label: "Material texture".into(),
+        dimension: wgpu::TextureDimension::D2,
+        size: extent,
+        format,
+        sample_count: 1,
+        mip_level_count: 1,
+        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[]
+    };
+
+    let texture = device.create_texture(&descriptor);
+
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All
+        },
+        texture_rgba,
+        TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(tex_width * 4),
+            rows_per_image: Some(tex_height)
+        },
+        extent
+    );
+
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+#[derive(Default)]
+struct Registry {
+    // Decoded files, `None` for ones that failed to load. Kept across
+    // device loss so textures can be recreated without touching the disk.
+    images: HashMap<PathBuf, Option<Arc<image::RgbaImage>>>,
+    files: HashMap<(PathBuf, bool), wgpu::TextureView>,
+    builtins: HashMap<BuiltinTexture, wgpu::TextureView>,
+}
+
+impl Registry {
+    fn builtin(&mut self, device: &wgpu::Device, queue: &wgpu::Queue,
+               builtin: BuiltinTexture) -> wgpu::TextureView {
+        self.builtins
+            .entry(builtin)
+            .or_insert_with(|| {
+                make_texture(device, queue, &builtin.image(), wgpu::TextureFormat::Rgba8Unorm)
+            })
+            .clone()
+    }
+
+    fn image(&mut self, path: &Path) -> Option<Arc<image::RgbaImage>> {
+        self.images
+            .entry(path.to_path_buf())
+            .or_insert_with(|| match load_image(path) {
+                Ok(image) => Some(Arc::new(image)),
+                Err(err) => {
+                    log::warn!("{err:#}, using a placeholder");
+                    None
+                }
+            })
+            .clone()
+    }
+}
+
+/// Material textures shared by every material on a device, loaded once
+/// per file. Owned by `Gpu`.
+#[derive(Default)]
+pub struct TextureRegistry {
+    inner: Mutex<Registry>,
+}
+
+impl TextureRegistry {
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        pipeline::lock(&self.inner)
+    }
+
+    pub fn builtin(&self, device: &wgpu::Device, queue: &wgpu::Queue,
+                   builtin: BuiltinTexture) -> wgpu::TextureView {
+        self.lock().builtin(device, queue, builtin)
+    }
+
+    /// Texture holding the image at `path`, in an sRGB format for color
+    /// data. Color files that can't be loaded show up as
+    /// `BuiltinTexture::Missing`, while data ones like normal maps get
+    /// `fallback` so they don't throw the shading off.
+    pub fn load(&self, device: &wgpu::Device, queue: &wgpu::Queue,
+                path: &Path, srgb: bool, fallback: BuiltinTexture) -> wgpu::TextureView {
+        let mut registry = self.lock();
+
+        let key = (path.to_path_buf(), srgb);
+        if let Some(view) = registry.files.get(&key) {
+            return view.clone();
+        }
+
+        let Some(image) = registry.image(path) else {
+            let placeholder = if srgb { BuiltinTexture::Missing } else { fallback };
+            return registry.builtin(device, queue, placeholder);
+        };
+
+        let format = if srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+
+        let view = make_texture(device, queue, &image, format);
+        registry.files.insert(key, view.clone());
+
+        view
+    }
+
+    /// Like `load` for `Some` paths, `fallback` otherwise.
+    pub fn load_or(&self, device: &wgpu::Device, queue: &wgpu::Queue,
+                   path: Option<&Path>, srgb: bool, fallback: BuiltinTexture)
+                   -> wgpu::TextureView {
+        match path {
+            Some(path) => self.load(device, queue, path, srgb, fallback),
+            None => self.builtin(device, queue, fallback),
+        }
+    }
+
+    /// Forgets every texture of a lost device, keeping the decoded images.
+    pub(crate) fn clear_textures(&self) {
+        let mut registry = self.lock();
+        registry.files.clear();
+        registry.builtins.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search() -> TextureSearch {
+        TextureSearch::new().with_root("/assets").with_root("/more")
+    }
+
+    #[test]
+    fn relative_names_try_mtl_dir_then_roots() {
+        let candidates = search().candidates(Path::new("/models/cube"), Path::new("wood.png"));
+
+        assert_eq!(candidates, [
+            PathBuf::from("/models/cube/wood.png"),
+            PathBuf::from("/assets/wood.png"),
+            PathBuf::from("/more/wood.png"),
+        ]);
+    }
+
+    #[test]
+    fn absolute_names_fall_back_to_file_name() {
+        let candidates = search().candidates(Path::new("/models/cube"),
+                                             Path::new("/home/someone/wood.png"));
+
+        assert_eq!(candidates, [
+            PathBuf::from("/home/someone/wood.png"),
+            PathBuf::from("/models/cube/wood.png"),
+            PathBuf::from("/assets/wood.png"),
+            PathBuf::from("/more/wood.png"),
+        ]);
+    }
+
+    #[test]
+    fn resolve_finds_first_existing_file() {
+        let dir = std::env::temp_dir().join(format!("webgpu-texture-search-{}", std::process::id()));
+        let (mtl_dir, root) = (dir.join("mtl"), dir.join("root"));
+        std::fs::create_dir_all(&mtl_dir).unwrap();
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("wood.png"), []).unwrap();
+
+        let search = TextureSearch::new().with_root(&root);
+        let found = search.resolve(&mtl_dir, "/elsewhere/wood.png");
+        let missing = search.resolve(&mtl_dir, "stone.png");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found.unwrap(), root.join("wood.png"));
+        let err = missing.unwrap_err().to_string();
+        assert!(err.contains(&mtl_dir.join("stone.png").display().to_string()), "{err}");
+        assert!(err.contains(&root.join("stone.png").display().to_string()), "{err}");
+    }
+}
@@ -0,0 +1,262 @@
+use std::ops::Mul;
+use glam::{Mat3, Mat4, Quat, Vec3};
+
+/// Translation, rotation and scale applied in that order from the outside
+/// in, i.e. a point is scaled first, then rotated, then moved. Like the
+/// camera it treats +Z as forward and +Y as up.
+///
+/// Composition and inversion are exact for uniform scale. With
+/// non-uniform scale under a rotation the result can't be represented
+/// without shear, so go through `Mat4` if that matters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self { translation, ..Self::IDENTITY }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self { rotation, ..Self::IDENTITY }
+    }
+
+    pub fn from_scale(scale: Vec3) -> Self {
+        Self { scale, ..Self::IDENTITY }
+    }
+
+    /// Decomposes `matrix`, which must not contain shear or projection.
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self { translation, rotation, scale }
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    pub fn with_translation(mut self, translation: Vec3) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * Vec3::Z
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::X
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::Y
+    }
+
+    pub fn translate(&mut self, offset: Vec3) {
+        self.translation += offset;
+    }
+
+    /// Rotates around `axis` in the parent's space, keeping the position.
+    pub fn rotate_axis(&mut self, axis: Vec3, angle: f32) {
+        self.rotation = Quat::from_axis_angle(axis.normalize(), angle) * self.rotation;
+    }
+
+    /// Rotates around `axis` in the transform's own space.
+    pub fn rotate_local_axis(&mut self, axis: Vec3, angle: f32) {
+        self.rotation *= Quat::from_axis_angle(axis.normalize(), angle);
+    }
+
+    /// Rotates position and orientation around `point`.
+    pub fn rotate_around(&mut self, point: Vec3, rotation: Quat) {
+        self.translation = point + rotation * (self.translation - point);
+        self.rotation = rotation * self.rotation;
+    }
+
+    /// Turns the forward axis towards `target`, with `up` as the
+    /// approximate up direction.
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) {
+        self.look_to(target - self.translation, up);
+    }
+
+    pub fn look_to(&mut self, direction: Vec3, up: Vec3) {
+        let Some(forward) = direction.try_normalize() else {
+            return;
+        };
+        // Looking along `up` leaves no way to tell right from left
+        let Some(right) = up.cross(forward).try_normalize() else {
+            return;
+        };
+
+        let up = forward.cross(right);
+        self.rotation = Quat::from_mat3(&Mat3::from_cols(right, up, forward));
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.translation + self.rotation * (self.scale * point)
+    }
+
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.rotation * (self.scale * vector)
+    }
+
+    /// Applies `child` first and then `self`, like `self.to_matrix() *
+    /// child.to_matrix()`.
+    pub fn mul_transform(&self, child: &Transform) -> Self {
+        Self {
+            translation: self.transform_point(child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale * child.scale,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.inverse();
+        let scale = self.scale.recip();
+
+        Self {
+            translation: scale * (rotation * -self.translation),
+            rotation,
+            scale,
+        }
+    }
+
+    /// Interpolates every component linearly, renormalizing the rotation.
+    /// Cheaper than `slerp` and close enough for nearby rotations.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.lerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+
+    /// Like `lerp`, but rotates at a constant angular speed.
+    pub fn slerp(&self, other: &Transform, t: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, child: Transform) -> Transform {
+        self.mul_transform(&child)
+    }
+}
+
+impl From<Transform> for Mat4 {
+    fn from(transform: Transform) -> Mat4 {
+        transform.to_matrix()
+    }
+}
+
+impl From<Mat4> for Transform {
+    fn from(matrix: Mat4) -> Transform {
+        Transform::from_matrix(matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn sample() -> Transform {
+        Transform {
+            translation: Vec3::new(1.0, -2.0, 3.0),
+            rotation: Quat::from_euler(glam::EulerRot::YXZ, 0.3, -1.1, 0.7),
+            scale: Vec3::splat(2.5),
+        }
+    }
+
+    fn assert_close(a: Transform, b: Transform) {
+        assert!(a.translation.abs_diff_eq(b.translation, EPSILON), "{a:?} != {b:?}");
+        // q and -q are the same rotation
+        assert!(a.rotation.dot(b.rotation).abs() > 1.0 - EPSILON, "{a:?} != {b:?}");
+        assert!(a.scale.abs_diff_eq(b.scale, EPSILON), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn matrix_round_trip() {
+        let transform = sample();
+        let matrix = Mat4::from(transform);
+
+        assert_close(Transform::from(matrix), transform);
+        assert!(matrix.transform_point3(Vec3::ONE)
+                .abs_diff_eq(transform.transform_point(Vec3::ONE), EPSILON));
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        let transform = sample();
+        let point = Vec3::new(-4.0, 0.5, 2.0);
+
+        let back = transform.inverse().transform_point(transform.transform_point(point));
+        assert!(back.abs_diff_eq(point, EPSILON));
+        assert_close(transform * transform.inverse(), Transform::IDENTITY);
+        assert!(transform.inverse().to_matrix()
+                .abs_diff_eq(transform.to_matrix().inverse(), EPSILON));
+    }
+
+    #[test]
+    fn mul_matches_matrix_product() {
+        let parent = sample();
+        let child = Transform::from_translation(Vec3::new(0.0, 1.0, -1.0))
+            .with_rotation(Quat::from_rotation_z(0.4))
+            .with_scale(Vec3::splat(0.5));
+
+        let product = parent.to_matrix() * child.to_matrix();
+        assert!(parent.mul_transform(&child).to_matrix().abs_diff_eq(product, EPSILON));
+    }
+
+    #[test]
+    fn look_at_points_forward_at_target() {
+        let mut transform = Transform::from_translation(Vec3::new(1.0, 2.0, 3.0));
+        let target = Vec3::new(-2.0, 0.0, 5.0);
+        transform.look_at(target, Vec3::Y);
+
+        let direction = (target - transform.translation).normalize();
+        assert!(transform.forward().abs_diff_eq(direction, EPSILON));
+        assert!(transform.right().dot(Vec3::Y).abs() < EPSILON);
+        assert!(transform.up().y > 0.0);
+    }
+
+    #[test]
+    fn look_at_along_up_keeps_rotation() {
+        let mut transform = sample();
+        let rotation = transform.rotation;
+        transform.look_at(transform.translation + Vec3::Y, Vec3::Y);
+
+        assert_eq!(transform.rotation, rotation);
+    }
+}
@@ -0,0 +1,123 @@
+use std::num::NonZero;
+use bytemuck::NoUninit;
+
+/// Per-draw uniforms packed into one buffer and bound with dynamic
+/// offsets. The data is collected on the CPU while the pass is recorded
+/// and uploaded in one go before it runs.
+struct UniformRing {
+    buffer: wgpu::Buffer,
+    data: Vec<u8>,
+    alignment: u64,
+    overflowed: bool,
+}
+
+/// Uploads recorded while a frame is being drawn. Everything written here
+/// is copied on the GPU before the frame's render pass executes, so unlike
+/// `Queue::write_buffer` inside the pass every draw sees its own data.
+pub struct FrameUploads {
+    device: wgpu::Device,
+    belt: wgpu::util::StagingBelt,
+    encoder: Option<wgpu::CommandEncoder>,
+    uniforms: UniformRing,
+}
+
+impl FrameUploads {
+    pub(crate) fn new(device: &wgpu::Device, uniform_ring_size: u64) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: "Uniform ring buffer".into(),
+            size: uniform_ring_size,
+            mapped_at_creation: false,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM
+        });
+
+        Self {
+            device: device.clone(),
+            belt: wgpu::util::StagingBelt::new(uniform_ring_size),
+            encoder: None,
+            uniforms: UniformRing {
+                buffer,
+                data: Vec::new(),
+                alignment: device.limits().min_uniform_buffer_offset_alignment as u64,
+                overflowed: false,
+            },
+        }
+    }
+
+    /// Binding for a bind group entry that's used with the offsets returned
+    /// by `push_uniform`. Its layout needs `has_dynamic_offset: true`.
+    pub fn uniform_binding(&self, size: u64) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.uniforms.buffer,
+            offset: 0,
+            size: NonZero::new(size)
+        })
+    }
+
+    /// Queues `value` for this frame and returns the dynamic offset to bind
+    /// it at, or `None` once the ring buffer is full.
+    pub fn push_uniform<T: NoUninit>(&mut self, value: &T) -> Option<u32> {
+        let ring = &mut self.uniforms;
+        let bytes = bytemuck::bytes_of(value);
+        let offset = ring.data.len() as u64;
+        let stride = (bytes.len() as u64).div_ceil(ring.alignment) * ring.alignment;
+
+        if offset + stride > ring.buffer.size() {
+            ring.overflowed = true;
+            return None;
+        }
+
+        ring.data.extend_from_slice(bytes);
+        ring.data.resize((offset + stride) as usize, 0);
+
+        Some(offset as u32)
+    }
+
+    /// Copies `data` into `target` through the staging belt before this
+    /// frame's render pass runs.
+    pub fn write_buffer(&mut self, target: &wgpu::Buffer, offset: u64, data: &[u8]) {
+        let Some(size) = NonZero::new(data.len() as u64) else {
+            return;
+        };
+
+        let device = self.device.clone();
+        let encoder = self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Upload Encoder"),
+            })
+        });
+
+        self.belt.write_buffer(encoder, target, offset, size, &device)
+            .copy_from_slice(data);
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        // Lets staging chunks from earlier frames return to the belt
+        let _ = self.device.poll(wgpu::PollType::Poll);
+
+        self.uniforms.data.clear();
+        self.uniforms.overflowed = false;
+    }
+
+    /// Records the uniform upload and returns the commands that have to be
+    /// submitted ahead of the frame's render pass.
+    pub(crate) fn finish(&mut self) -> Option<wgpu::CommandBuffer> {
+        if self.uniforms.overflowed {
+            log::warn!("Uniform ring buffer of {} bytes is full, some draws were skipped",
+                       self.uniforms.buffer.size());
+        }
+
+        let data = std::mem::take(&mut self.uniforms.data);
+        let buffer = self.uniforms.buffer.clone();
+        self.write_buffer(&buffer, 0, &data);
+        self.uniforms.data = data;
+
+        self.belt.finish();
+        self.encoder.take().map(|encoder| encoder.finish())
+    }
+
+    /// Reclaims staging memory, call once the commands from `finish` were
+    /// submitted.
+    pub(crate) fn recall(&mut self) {
+        self.belt.recall();
+    }
+}